@@ -0,0 +1,76 @@
+use syn::ext::IdentExt;
+use syn::parse::{Parse, ParseStream};
+use syn::punctuated::Punctuated;
+use syn::{Attribute, Expr, Ident, Path, Token};
+
+pub struct BuilderAttr {
+    pub name: Ident,
+    pub value: Option<Expr>,
+}
+
+impl BuilderAttr {
+    pub fn is(&self, name: &str) -> bool {
+        self.name == name
+    }
+
+    pub fn ident_value(&self) -> syn::Result<Option<Ident>> {
+        match &self.value {
+            None => Ok(None),
+            Some(Expr::Path(path)) if path.qself.is_none() => path
+                .path
+                .get_ident()
+                .cloned()
+                .map(Some)
+                .ok_or_else(|| syn::Error::new_spanned(path, "expected an identifier")),
+            Some(Expr::Lit(syn::ExprLit {
+                lit: syn::Lit::Str(lit),
+                ..
+            })) => lit.parse().map(Some),
+            Some(value) => Err(syn::Error::new_spanned(value, "expected an identifier")),
+        }
+    }
+
+    pub fn path_value(&self) -> syn::Result<Option<Path>> {
+        match &self.value {
+            None => Ok(None),
+            Some(Expr::Path(path)) if path.qself.is_none() => Ok(Some(path.path.clone())),
+            Some(Expr::Lit(syn::ExprLit {
+                lit: syn::Lit::Str(lit),
+                ..
+            })) => lit.parse().map(Some),
+            Some(value) => Err(syn::Error::new_spanned(value, "expected a path")),
+        }
+    }
+
+    pub fn unexpected(&self) -> syn::Error {
+        syn::Error::new(
+            self.name.span(),
+            format!("unknown builder attribute `{}`", self.name),
+        )
+    }
+}
+
+impl Parse for BuilderAttr {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let name = input.call(Ident::parse_any)?;
+        let value = if input.peek(Token![=]) {
+            input.parse::<Token![=]>()?;
+            Some(input.parse()?)
+        } else {
+            None
+        };
+        Ok(BuilderAttr { name, value })
+    }
+}
+
+pub fn parse(attrs: &[Attribute]) -> syn::Result<Vec<BuilderAttr>> {
+    let mut parsed = Vec::new();
+    for attr in attrs {
+        if attr.path.is_ident("builder") {
+            parsed.extend(
+                attr.parse_args_with(Punctuated::<BuilderAttr, Token![,]>::parse_terminated)?,
+            );
+        }
+    }
+    Ok(parsed)
+}
@@ -1,7 +1,41 @@
+use proc_macro2::{Span, TokenStream};
+
 #[derive(Debug)]
 pub enum Error {
     InvalidShape(&'static str, &'static str),
     UnexpectedIdent,
     MissingIdent,
+    Syn(syn::Error),
     Multiple(Vec<Error>),
 }
+
+impl Error {
+    pub fn to_compile_error(&self) -> TokenStream {
+        match self {
+            Error::InvalidShape(expected, found) => syn::Error::new(
+                Span::call_site(),
+                format!(
+                    "`Builder` can only be derived for a {}, not a {}",
+                    expected, found
+                ),
+            )
+            .to_compile_error(),
+            Error::UnexpectedIdent => {
+                syn::Error::new(Span::call_site(), "unexpected field name in tuple struct")
+                    .to_compile_error()
+            }
+            Error::MissingIdent => {
+                syn::Error::new(Span::call_site(), "missing field name in struct")
+                    .to_compile_error()
+            }
+            Error::Syn(error) => error.to_compile_error(),
+            Error::Multiple(errors) => errors.iter().map(Error::to_compile_error).collect(),
+        }
+    }
+}
+
+impl From<syn::Error> for Error {
+    fn from(error: syn::Error) -> Self {
+        Error::Syn(error)
+    }
+}
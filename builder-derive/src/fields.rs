@@ -1,25 +1,59 @@
+use std::borrow::Cow;
+use std::iter::once;
+
 use convert_case::{Case, Casing};
-use proc_macro2::TokenStream;
+use proc_macro2::{Delimiter, Group, Literal, Span, TokenStream, TokenTree};
 use quote::{format_ident, quote, ToTokens};
-use syn::{Ident, Type};
+use syn::visit_mut::{self, VisitMut};
+use syn::{
+    Arm, Block, Expr, ExprClosure, ExprForLoop, ExprIf, ExprWhile, GenericArgument, Ident, Macro,
+    Pat, Path, PathArguments, Stmt, Type,
+};
 
+use crate::attrs;
 use crate::error::Error;
 
-pub enum Fields<'a> {
+pub struct Fields<'a> {
+    pub shape: Shape<'a>,
+    /// The state of each field group, which the builder stores in place of its members.
+    groups: Vec<Field<'a>>,
+}
+
+pub enum Shape<'a> {
     Named(Vec<NamedField<'a>>),
     Unnamed(Vec<UnnamedField<'a>>),
     Unit,
 }
 
 impl<'a> Fields<'a> {
-    pub fn fields(&'a self) -> impl Iterator<Item = &'a Field<'a>> + 'a {
-        match self {
-            Fields::Named(fields) => Iter::Named(fields.iter().map(|f| &f.field)),
-            Fields::Unnamed(fields) => Iter::Unnamed(fields.iter().map(|f| &f.field)),
-            Fields::Unit => Iter::Unit,
+    /// Every field of the struct, including skipped and grouped ones.
+    pub fn all(&'a self) -> impl Iterator<Item = &'a Field<'a>> + 'a {
+        match &self.shape {
+            Shape::Named(fields) => Iter::Named(fields.iter().map(|f| &f.field)),
+            Shape::Unnamed(fields) => Iter::Unnamed(fields.iter().map(|f| &f.field)),
+            Shape::Unit => Iter::Unit,
         }
     }
 
+    /// The fields which are part of the builder, followed by the field groups.
+    pub fn fields(&'a self) -> impl Iterator<Item = &'a Field<'a>> + 'a {
+        self.all()
+            .filter(|field| !field.skip && field.member.is_none())
+            .chain(&self.groups)
+    }
+
+    /// The group a field belongs to, and the field's position within it.
+    pub fn group_of(&'a self, field: &Field) -> Option<(&'a Field<'a>, usize)> {
+        let member = field.member.as_ref()?;
+        let group = self
+            .groups
+            .iter()
+            .find(|group| group.name == member.group.to_string())?;
+        let members = group.group.as_ref()?;
+        let position = members.iter().position(|m| m.name == field.name)?;
+        Some((group, position))
+    }
+
     pub fn fields_except<T>(
         &'a self,
         except_idx: usize,
@@ -46,8 +80,15 @@ impl<'a> Fields<'a> {
     }
 
     pub fn default_constructors(&'a self) -> impl Iterator<Item = TokenStream> + 'a {
-        self.fields()
-            .map(|Field { field_ident, .. }| quote!(#field_ident: ::builder::NoData::new()))
+        self.fields().map(|field| {
+            let field_ident = &field.field_ident;
+            if field.flatten {
+                let ty = &field.ty;
+                quote!(#field_ident: <#ty as ::builder::Builder>::builder())
+            } else {
+                quote!(#field_ident: ::builder::NoData::new())
+            }
+        })
     }
 
     pub fn generics(&'a self) -> impl Iterator<Item = TokenStream> + 'a {
@@ -55,21 +96,282 @@ impl<'a> Fields<'a> {
             .map(|field| field.generic_ident.to_token_stream())
     }
 
+    pub fn names(&'a self) -> impl Iterator<Item = TokenStream> + 'a {
+        let in_builder = |field: &Field| !field.skip && field.member.is_none();
+        let names = match &self.shape {
+            Shape::Named(fields) => Iter::Named(
+                fields
+                    .iter()
+                    .filter(move |f| in_builder(&f.field))
+                    .map(|f| f.ident.to_token_stream()),
+            ),
+            Shape::Unnamed(fields) => Iter::Unnamed(
+                fields
+                    .iter()
+                    .filter(move |f| in_builder(&f.field))
+                    .map(|f| Literal::usize_unsuffixed(f.idx).to_token_stream()),
+            ),
+            Shape::Unit => Iter::Unit,
+        };
+        names.chain(
+            self.groups
+                .iter()
+                .map(|group| format_ident!("{}", group.name).to_token_stream()),
+        )
+    }
+
     pub fn no_data_generics(&'a self) -> impl Iterator<Item = TokenStream> + 'a {
-        self.fields()
-            .map(|Field { ty, .. }| quote!(::builder::NoData<#ty>))
+        self.fields().map(|Field { ty, flatten, .. }| {
+            if *flatten {
+                quote!(<#ty as ::builder::Builder>::Builder)
+            } else {
+                quote!(::builder::NoData<#ty>)
+            }
+        })
     }
 
-    pub fn completed_generics(&'a self) -> impl Iterator<Item = TokenStream> + 'a {
-        self.fields().map(|field| if field.default {
-            field.generic_ident.to_token_stream()
+    /// The indices (into `all`) of the fields with computed defaults, ordered so that each one
+    /// comes after the computed defaults it reads.
+    pub fn default_order(&'a self) -> syn::Result<Vec<usize>> {
+        let fields = self.all().collect::<Vec<_>>();
+        let mut visited = vec![Visit::New; fields.len()];
+        let mut order = Vec::new();
+        for i in 0..fields.len() {
+            visit_default(&fields, i, &mut visited, &mut Vec::new(), &mut order)?;
+        }
+        Ok(order)
+    }
+
+    /// The `requires` and `conflicts_with` relations between fields, as indices into `fields`.
+    pub fn relations(&'a self) -> syn::Result<Vec<Relation>> {
+        let fields = self.fields().collect::<Vec<_>>();
+        let mut relations = Vec::new();
+        for (i, field) in fields.iter().enumerate() {
+            let related = field
+                .requires
+                .iter()
+                .map(|other| (RelationKind::Requires, other))
+                .chain(
+                    field
+                        .conflicts_with
+                        .iter()
+                        .map(|other| (RelationKind::ConflictsWith, other)),
+                );
+            for (kind, other) in related {
+                let j = fields
+                    .iter()
+                    .position(|field| {
+                        field.name == other.to_string() && !field.flatten && field.group.is_none()
+                    })
+                    .ok_or_else(|| {
+                        syn::Error::new(other.span(), format!("no field named `{}`", other))
+                    })?;
+                if i == j {
+                    return Err(syn::Error::new(
+                        other.span(),
+                        "a field cannot be related to itself",
+                    ));
+                }
+                relations.push(Relation {
+                    kind,
+                    field: i,
+                    other: j,
+                });
+            }
+        }
+        Ok(relations)
+    }
+
+    /// Checks that every field of a `#[non_exhaustive]` struct either has a default or is marked
+    /// `required`, so that a field added later cannot be required by accident.
+    pub fn check_non_exhaustive(&'a self) -> Result<(), Error> {
+        let errors = self
+            .fields()
+            .filter(|field| !(field.default || field.flatten || field.required))
+            .map(|field| {
+                let message = format!(
+                    "`{}` needs a default, as the struct is `#[non_exhaustive]`; mark it `#[builder(required)]` if it has always been required",
+                    field.name
+                );
+                match field.ty {
+                    Cow::Borrowed(ty) => syn::Error::new_spanned(ty, message),
+                    Cow::Owned(_) => syn::Error::new(Span::call_site(), message),
+                }
+                .into()
+            })
+            .collect::<Vec<_>>();
+        if errors.is_empty() {
+            Ok(())
         } else {
-            field.ty.to_token_stream()
+            Err(Error::Multiple(errors))
+        }
+    }
+
+    /// The required slots of a `steps` builder, as indices into `fields`, in the order their
+    /// setters must be called. A group takes the place of its first member.
+    pub fn steps(&'a self, span: Span) -> syn::Result<Vec<usize>> {
+        let fields = self.fields().collect::<Vec<_>>();
+        let mut steps = Vec::new();
+        for field in self.all().filter(|field| !field.skip) {
+            if field.flatten {
+                return Err(syn::Error::new(
+                    span,
+                    format!(
+                        "`{}` cannot be flattened into a `steps` builder",
+                        field.name
+                    ),
+                ));
+            }
+            let slot = match self.group_of(field) {
+                Some((group, _)) => group,
+                None => field,
+            };
+            let i = fields
+                .iter()
+                .position(|field| field.field_ident == slot.field_ident)
+                .expect("every slot is part of the builder");
+            if !slot.default && !steps.contains(&i) {
+                steps.push(i);
+            }
+        }
+        Ok(steps)
+    }
+
+    pub fn completed_generics(&'a self) -> impl Iterator<Item = TokenStream> + 'a {
+        self.fields().map(|field| {
+            if field.default || field.flatten {
+                field.generic_ident.to_token_stream()
+            } else {
+                field.ty.to_token_stream()
+            }
         })
     }
 }
 
+#[derive(Clone, Copy, PartialEq)]
+pub enum RelationKind {
+    Requires,
+    ConflictsWith,
+}
+
+pub struct Relation {
+    pub kind: RelationKind,
+    pub field: usize,
+    pub other: usize,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Visit {
+    New,
+    InProgress,
+    Done,
+}
+
+fn visit_default(
+    fields: &[&Field],
+    i: usize,
+    visited: &mut [Visit],
+    path: &mut Vec<usize>,
+    order: &mut Vec<usize>,
+) -> syn::Result<()> {
+    if visited[i] == Visit::Done {
+        return Ok(());
+    }
+    let default_expr = match &fields[i].default_expr {
+        Some(default_expr) => default_expr,
+        None => {
+            visited[i] = Visit::Done;
+            return Ok(());
+        }
+    };
+    visited[i] = Visit::InProgress;
+    path.push(i);
+    for (name, local) in &default_expr.deps {
+        let dep = fields
+            .iter()
+            .position(|field| field.field_ident == *local)
+            .ok_or_else(|| syn::Error::new(local.span(), format!("no field named `{}`", name)))?;
+        if visited[dep] == Visit::InProgress {
+            let start = path.iter().position(|&k| k == dep).unwrap();
+            let cycle = path[start..]
+                .iter()
+                .chain(once(&dep))
+                .map(|&k| format!("`{}`", fields[k].name))
+                .collect::<Vec<_>>();
+            return Err(syn::Error::new(
+                local.span(),
+                format!("cyclic default: {}", cycle.join(" -> ")),
+            ));
+        }
+        visit_default(fields, dep, visited, path, order)?;
+    }
+    path.pop();
+    visited[i] = Visit::Done;
+    order.push(i);
+    Ok(())
+}
+
 impl<'a> TryFrom<&'a syn::Fields> for Fields<'a> {
+    type Error = Error;
+    fn try_from(fields: &'a syn::Fields) -> Result<Self, Self::Error> {
+        let shape = Shape::try_from(fields)?;
+        let mut groups = Vec::<Field>::new();
+        let members = match &shape {
+            Shape::Named(fields) => fields.iter().map(|f| &f.field).collect(),
+            Shape::Unnamed(fields) => fields.iter().map(|f| &f.field).collect(),
+            Shape::Unit => Vec::new(),
+        };
+        for field in members {
+            let member = match &field.member {
+                Some(member) => member,
+                None => continue,
+            };
+            let name = member.group.to_string();
+            let group = match groups.iter_mut().find(|group| group.name == name) {
+                Some(group) => group,
+                None => {
+                    groups.push(Field::group(&member.group));
+                    groups.last_mut().unwrap()
+                }
+            };
+            group.group.as_mut().unwrap().push(GroupMember {
+                name: field.name.clone(),
+                setter: field.setter.clone(),
+                ty: member.ty,
+            });
+            // a group must always be set, so marking any one member marks the group
+            group.required |= field.required;
+            match (group.mode, member.mode) {
+                (Some(mode), Some(other)) if mode != other => {
+                    return Err(syn::Error::new(
+                        member.group.span(),
+                        format!("field group `{}` has conflicting modes", name),
+                    )
+                    .into())
+                }
+                (None, mode) => group.mode = mode,
+                _ => {}
+            }
+        }
+        for group in &mut groups {
+            if group.mode.is_none() {
+                return Err(syn::Error::new(
+                    proc_macro2::Span::call_site(),
+                    format!(
+                        "field group `{}` needs `exactly_one` or `at_least_one`",
+                        group.name
+                    ),
+                )
+                .into());
+            }
+            let tys = group.group.as_ref().unwrap().iter().map(|member| member.ty);
+            group.ty = Cow::Owned(syn::parse_quote!((#(::core::option::Option<#tys>,)*)));
+        }
+        Ok(Fields { shape, groups })
+    }
+}
+
+impl<'a> TryFrom<&'a syn::Fields> for Shape<'a> {
     type Error = Error;
     fn try_from(fields: &'a syn::Fields) -> Result<Self, Self::Error> {
         match fields {
@@ -87,7 +389,7 @@ impl<'a> TryFrom<&'a syn::Fields> for Fields<'a> {
                     })
                     .collect();
                 if errors.is_empty() {
-                    Ok(Fields::Named(fields))
+                    Ok(Shape::Named(fields))
                 } else {
                     Err(Error::Multiple(errors))
                 }
@@ -107,12 +409,12 @@ impl<'a> TryFrom<&'a syn::Fields> for Fields<'a> {
                     })
                     .collect();
                 if errors.is_empty() {
-                    Ok(Fields::Unnamed(fields))
+                    Ok(Shape::Unnamed(fields))
                 } else {
                     Err(Error::Multiple(errors))
                 }
             }
-            syn::Fields::Unit => Ok(Fields::Unit),
+            syn::Fields::Unit => Ok(Shape::Unit),
         }
     }
 }
@@ -170,7 +472,7 @@ impl<'a> TryFrom<&'a syn::Field> for NamedField<'a> {
         let ident = field.ident.as_ref().ok_or(Error::MissingIdent)?;
         Ok(NamedField {
             ident,
-            field: Field::new(ident, field),
+            field: Field::new(ident, field)?,
         })
     }
 }
@@ -189,50 +491,520 @@ impl<'a> TryFrom<(usize, &'a syn::Field)> for UnnamedField<'a> {
         }
         Ok(UnnamedField {
             idx,
-            field: Field::new(&idx, field),
+            field: Field::new(&idx, field)?,
         })
     }
 }
 
 pub struct Field<'a> {
+    pub name: String,
     pub field_ident: Ident,
     pub setter: Ident,
+    pub getter: Ident,
     pub builder: Ident,
     pub generic_ident: Ident,
     pub default: bool,
+    pub default_expr: Option<DefaultExpr>,
+    /// The field has no setter and is always initialized from its default.
+    pub skip: bool,
+    /// The field's state is its own builder, whose setters are lifted into the parent builder.
+    pub flatten: bool,
+    pub prefix: Option<Ident>,
+    /// The group this field is a member of, if any.
+    pub member: Option<Member<'a>>,
+    /// For a field group, the members whose state it holds.
+    pub group: Option<Vec<GroupMember<'a>>>,
+    pub mode: Option<GroupMode>,
+    /// Fields which must be set if this one is.
+    pub requires: Vec<Ident>,
+    /// Fields which cannot be set together with this one.
+    pub conflicts_with: Vec<Ident>,
+    pub push: Option<Ident>,
+    pub insert: Option<Ident>,
+    pub field_trait: Option<FieldTrait>,
+    /// The setter accepts anything that converts into the field's type.
+    pub into: bool,
+    /// The field is deliberately left without a default, as `#[non_exhaustive]` structs require.
+    pub required: bool,
+    pub ty: Cow<'a, Type>,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum GroupMode {
+    ExactlyOne,
+    AtLeastOne,
+}
+
+pub struct Member<'a> {
+    pub group: Ident,
+    pub mode: Option<GroupMode>,
+    /// The type inside the member's `Option`.
+    pub ty: &'a Type,
+}
+
+pub struct GroupMember<'a> {
+    pub name: String,
+    pub setter: Ident,
     pub ty: &'a Type,
 }
 
+/// A default computed in `build`, which may read the final values of other fields.
+pub struct DefaultExpr {
+    /// The expression, with every `b.field` replaced by a read through a reference to that
+    /// field's local in `build`.
+    pub body: TokenStream,
+    /// The fields read by the expression, as written and as the local they were replaced with.
+    pub deps: Vec<(String, Ident)>,
+}
+
+impl DefaultExpr {
+    /// The expression, borrowing the fields it reads, so that they can still be moved into the
+    /// built value.
+    pub fn value(&self) -> TokenStream {
+        let mut locals = self.deps.iter().map(|(_, local)| local).collect::<Vec<_>>();
+        locals.sort();
+        locals.dedup();
+        let body = &self.body;
+        quote!({
+            #(let #locals = &#locals;)*
+            #body
+        })
+    }
+
+    fn new(expr: &Expr) -> syn::Result<Self> {
+        let closure = match expr {
+            Expr::Closure(closure) => closure,
+            expr => {
+                return Ok(DefaultExpr {
+                    body: expr.to_token_stream(),
+                    deps: Vec::new(),
+                })
+            }
+        };
+        let param = match closure.inputs.iter().collect::<Vec<_>>()[..] {
+            [Pat::Ident(pat)] => &pat.ident,
+            [Pat::Type(pat)] => match &*pat.pat {
+                Pat::Ident(pat) => &pat.ident,
+                pat => return Err(syn::Error::new_spanned(pat, "expected an identifier")),
+            },
+            _ => {
+                return Err(syn::Error::new_spanned(
+                    &closure.inputs,
+                    "a default closure takes exactly one argument",
+                ))
+            }
+        };
+        let mut accesses = FieldAccesses {
+            param,
+            deps: Vec::new(),
+        };
+        let mut body = (*closure.body).clone();
+        accesses.visit_expr_mut(&mut body);
+        Ok(DefaultExpr {
+            body: body.to_token_stream(),
+            deps: accesses.deps,
+        })
+    }
+}
+
+/// Replaces `param.field` with `(*field_{field})`, except where `param` is bound again: by a
+/// closure's parameters, or a `let`, `match`, `for`, `if let` or `while let` pattern.
+struct FieldAccesses<'a> {
+    param: &'a Ident,
+    deps: Vec<(String, Ident)>,
+}
+
+impl<'a> FieldAccesses<'a> {
+    fn rebinds(&self, pat: &Pat) -> bool {
+        contains_ident(pat.to_token_stream(), self.param)
+    }
+
+    fn rebinds_in(&self, expr: &Expr) -> bool {
+        matches!(expr, Expr::Let(expr) if self.rebinds(&expr.pat))
+    }
+}
+
+impl<'a> VisitMut for FieldAccesses<'a> {
+    fn visit_expr_mut(&mut self, expr: &mut Expr) {
+        if let Expr::Field(field) = expr {
+            let base = match &*field.base {
+                Expr::Path(base) => base.qself.is_none() && base.path.is_ident(self.param),
+                _ => false,
+            };
+            if base {
+                let (member, span) = match &field.member {
+                    syn::Member::Named(member) => (member.to_string(), member.span()),
+                    syn::Member::Unnamed(member) => (member.index.to_string(), member.span),
+                };
+                *expr = Expr::Verbatim(field_access(&member, span, &mut self.deps));
+                return;
+            }
+        }
+        visit_mut::visit_expr_mut(self, expr);
+    }
+
+    fn visit_expr_closure_mut(&mut self, closure: &mut ExprClosure) {
+        if !closure.inputs.iter().any(|pat| self.rebinds(pat)) {
+            visit_mut::visit_expr_closure_mut(self, closure);
+        }
+    }
+
+    fn visit_block_mut(&mut self, block: &mut Block) {
+        for stmt in &mut block.stmts {
+            self.visit_stmt_mut(stmt);
+            if matches!(stmt, Stmt::Local(local) if self.rebinds(&local.pat)) {
+                break;
+            }
+        }
+    }
+
+    fn visit_arm_mut(&mut self, arm: &mut Arm) {
+        if !self.rebinds(&arm.pat) {
+            visit_mut::visit_arm_mut(self, arm);
+        }
+    }
+
+    fn visit_expr_for_loop_mut(&mut self, expr: &mut ExprForLoop) {
+        self.visit_expr_mut(&mut expr.expr);
+        if !self.rebinds(&expr.pat) {
+            self.visit_block_mut(&mut expr.body);
+        }
+    }
+
+    fn visit_expr_if_mut(&mut self, expr: &mut ExprIf) {
+        self.visit_expr_mut(&mut expr.cond);
+        if !self.rebinds_in(&expr.cond) {
+            self.visit_block_mut(&mut expr.then_branch);
+        }
+        if let Some((_, else_branch)) = &mut expr.else_branch {
+            self.visit_expr_mut(else_branch);
+        }
+    }
+
+    fn visit_expr_while_mut(&mut self, expr: &mut ExprWhile) {
+        self.visit_expr_mut(&mut expr.cond);
+        if !self.rebinds_in(&expr.cond) {
+            self.visit_block_mut(&mut expr.body);
+        }
+    }
+
+    // a macro's input is only tokens, so its accesses are rewritten without seeing rebindings
+    fn visit_macro_mut(&mut self, mac: &mut Macro) {
+        mac.tokens = rewrite_field_accesses(mac.tokens.clone(), self.param, &mut self.deps);
+    }
+}
+
+fn contains_ident(tokens: TokenStream, ident: &Ident) -> bool {
+    tokens.into_iter().any(|token| match token {
+        TokenTree::Ident(token) => token == *ident,
+        TokenTree::Group(group) => contains_ident(group.stream(), ident),
+        _ => false,
+    })
+}
+
+/// A read of `member` through the reference to its local in `build`, recorded in `deps`.
+fn field_access(member: &str, span: Span, deps: &mut Vec<(String, Ident)>) -> TokenStream {
+    let local = Ident::new(&format!("field_{}", member.to_case(Case::Snake)), span);
+    deps.push((member.to_owned(), local.clone()));
+    let mut deref = Group::new(Delimiter::Parenthesis, quote!(*#local));
+    deref.set_span(span);
+    TokenTree::Group(deref).into()
+}
+
+/// Replaces `param.field` with `(*field_{field})` at the token level, for the input of macro
+/// invocations.
+fn rewrite_field_accesses(
+    tokens: TokenStream,
+    param: &Ident,
+    deps: &mut Vec<(String, Ident)>,
+) -> TokenStream {
+    let mut tokens = tokens.into_iter();
+    let mut rewritten = Vec::new();
+    while let Some(token) = tokens.next() {
+        match token {
+            TokenTree::Ident(ident) if ident == *param => {
+                let mut lookahead = tokens.clone();
+                let member = match (lookahead.next(), lookahead.next()) {
+                    (Some(TokenTree::Punct(dot)), Some(TokenTree::Ident(member)))
+                        if dot.as_char() == '.' =>
+                    {
+                        Some((member.to_string(), member.span()))
+                    }
+                    (Some(TokenTree::Punct(dot)), Some(TokenTree::Literal(member)))
+                        if dot.as_char() == '.' && member.to_string().parse::<usize>().is_ok() =>
+                    {
+                        Some((member.to_string(), member.span()))
+                    }
+                    _ => None,
+                };
+                match member {
+                    Some((member, span)) => {
+                        tokens = lookahead;
+                        rewritten.extend(field_access(&member, span, deps));
+                    }
+                    None => rewritten.push(TokenTree::Ident(ident)),
+                }
+            }
+            TokenTree::Group(group) => {
+                let mut new = Group::new(
+                    group.delimiter(),
+                    rewrite_field_accesses(group.stream(), param, deps),
+                );
+                new.set_span(group.span());
+                rewritten.push(TokenTree::Group(new));
+            }
+            token => rewritten.push(token),
+        }
+    }
+    rewritten.into_iter().collect()
+}
+
+pub struct FieldTrait {
+    /// The module the traits are defined in, or `None` if they should be defined alongside the
+    /// builder.
+    pub module: Option<Path>,
+    pub set: Ident,
+    pub has: Ident,
+}
+
 impl<'a> Field<'a> {
-    fn new<S: ToString>(suffix: &S, field: &'a syn::Field) -> Self {
+    /// The macro listing a flattened field's fields, which its derive exports under the builder's
+    /// name, `FooBuilder` next to `Foo`, so the builder has to be imported with the type.
+    pub fn flatten_macro(&self) -> syn::Result<Path> {
+        match &*self.ty {
+            Type::Path(ty) if ty.qself.is_none() => {
+                let mut path = ty.path.clone();
+                let last = path.segments.last_mut().unwrap();
+                last.ident = format_ident!("{}Builder", last.ident);
+                last.arguments = PathArguments::None;
+                Ok(path)
+            }
+            ty => Err(syn::Error::new_spanned(
+                ty,
+                "a flattened field must name a struct",
+            )),
+        }
+    }
+
+    fn new<S: ToString>(suffix: &S, field: &'a syn::Field) -> Result<Self, Error> {
         let suffix = suffix.to_string();
         let snake_suffix = suffix.to_case(Case::Snake);
         let camel_suffix = suffix.to_case(Case::UpperCamel);
-        Field {
+        let mut default = false;
+        let mut default_expr = None;
+        let mut skip = false;
+        let mut flatten = false;
+        let mut prefix = None;
+        let mut group = None;
+        let mut mode = None;
+        let mut requires = Vec::new();
+        let mut conflicts_with = Vec::new();
+        let mut push = None;
+        let mut insert = None;
+        let mut field_trait = None;
+        let mut setter = None;
+        let mut into = false;
+        let mut required = false;
+        for attr in attrs::parse(&field.attrs)? {
+            if attr.is("default") {
+                default = true;
+                default_expr = attr.value.as_ref().map(DefaultExpr::new).transpose()?;
+            } else if attr.is("skip") {
+                skip = true;
+                default_expr = attr.value.as_ref().map(DefaultExpr::new).transpose()?;
+            } else if attr.is("flatten") {
+                flatten = true;
+            } else if attr.is("prefix") {
+                prefix = Some(attr.ident_value()?.ok_or_else(|| {
+                    syn::Error::new(attr.name.span(), "expected `prefix = name`")
+                })?);
+            } else if attr.is("group") {
+                group = Some(attr.ident_value()?.ok_or_else(|| {
+                    syn::Error::new(attr.name.span(), "expected `group = \"name\"`")
+                })?);
+            } else if attr.is("exactly_one") {
+                mode = Some(GroupMode::ExactlyOne);
+            } else if attr.is("at_least_one") {
+                mode = Some(GroupMode::AtLeastOne);
+            } else if attr.is("requires") || attr.is("conflicts_with") {
+                let other = attr.ident_value()?.ok_or_else(|| {
+                    syn::Error::new(
+                        attr.name.span(),
+                        format!("expected `{} = field`", attr.name),
+                    )
+                })?;
+                if attr.is("requires") {
+                    requires.push(other);
+                } else {
+                    conflicts_with.push(other);
+                }
+            } else if attr.is("push") {
+                let name = attr
+                    .ident_value()?
+                    .map_or_else(|| snake_suffix.clone(), |ident| ident.to_string());
+                push = Some(format_ident!("push_{}", name));
+            } else if attr.is("insert") {
+                let name = attr
+                    .ident_value()?
+                    .map_or_else(|| snake_suffix.clone(), |ident| ident.to_string());
+                insert = Some(format_ident!("insert_{}", name));
+            } else if attr.is("setter") {
+                setter = Some(attr.ident_value()?.ok_or_else(|| {
+                    syn::Error::new(attr.name.span(), "expected `setter = name`")
+                })?);
+            } else if attr.is("into") {
+                into = true;
+            } else if attr.is("required") {
+                required = true;
+            } else if attr.is("field_trait") {
+                field_trait = Some(FieldTrait {
+                    module: attr.path_value()?,
+                    set: format_ident!("Set{}", camel_suffix),
+                    has: format_ident!("Has{}", camel_suffix),
+                });
+            } else {
+                return Err(attr.unexpected().into());
+            }
+        }
+        let own_setter = setter.is_some() || into;
+        if skip
+            && (default
+                || push.is_some()
+                || insert.is_some()
+                || field_trait.is_some()
+                || own_setter)
+        {
+            return Err(syn::Error::new_spanned(
+                field,
+                "a skipped field cannot have other builder attributes",
+            )
+            .into());
+        }
+        if flatten
+            && (skip
+                || default
+                || push.is_some()
+                || insert.is_some()
+                || field_trait.is_some()
+                || own_setter)
+        {
+            return Err(syn::Error::new_spanned(
+                field,
+                "a flattened field cannot have other builder attributes",
+            )
+            .into());
+        }
+        let member = match group {
+            Some(group) => {
+                if skip || flatten || default || push.is_some() || insert.is_some() || into {
+                    return Err(syn::Error::new_spanned(
+                        field,
+                        "a grouped field cannot have other builder attributes",
+                    )
+                    .into());
+                }
+                Some(Member {
+                    group,
+                    mode,
+                    ty: option_inner(&field.ty).ok_or_else(|| {
+                        syn::Error::new_spanned(&field.ty, "a grouped field must be an `Option`")
+                    })?,
+                })
+            }
+            None if mode.is_some() => {
+                return Err(syn::Error::new_spanned(field, "a group mode requires `group`").into())
+            }
+            None => None,
+        };
+        if (skip || flatten || member.is_some())
+            && !(requires.is_empty() && conflicts_with.is_empty())
+        {
+            return Err(syn::Error::new_spanned(
+                field,
+                "only a field with its own setter can have `requires` or `conflicts_with`",
+            )
+            .into());
+        }
+        if required && (skip || flatten || default || push.is_some() || insert.is_some()) {
+            return Err(
+                syn::Error::new_spanned(field, "a required field cannot have a default").into(),
+            );
+        }
+        if let (Some(prefix), false) = (&prefix, flatten) {
+            return Err(syn::Error::new(prefix.span(), "`prefix` requires `flatten`").into());
+        }
+        Ok(Field {
+            name: suffix.clone(),
             field_ident: format_ident!("field_{}", snake_suffix),
-            setter: format_ident!("set_{}", snake_suffix),
+            setter: setter.unwrap_or_else(|| format_ident!("set_{}", snake_suffix)),
+            getter: format_ident!("get_{}", snake_suffix),
             builder: format_ident!("build_{}", snake_suffix),
             generic_ident: format_ident!("__Field{}", camel_suffix),
-            default: field
-                .attrs
-                .iter()
-                .any(|attr| {
-                    use syn::Meta;
-                    if let Ok(Meta::List(list)) = attr.parse_meta() {
-                        list.path.is_ident::<Ident>(&syn::parse_quote!(builder))
-                            && list.nested.iter().any(|meta| {
-                                use syn::NestedMeta;
-                                if let NestedMeta::Meta(Meta::Path(p)) = meta {
-                                    p.is_ident::<Ident>(&syn::parse_quote!(default))
-                                } else {
-                                    false
-                                }
-                            })
-                    } else {
-                        false
-                    }
-                }),
-            ty: &field.ty,
+            // a collection that nothing has been pushed to is empty
+            default: default || push.is_some() || insert.is_some(),
+            default_expr,
+            skip,
+            flatten,
+            prefix,
+            member,
+            group: None,
+            mode: None,
+            requires,
+            conflicts_with,
+            push,
+            insert,
+            field_trait,
+            into,
+            required,
+            ty: Cow::Borrowed(&field.ty),
+        })
+    }
+
+    /// The builder state of a field group, whose type is filled in once its members are known.
+    fn group(name: &Ident) -> Self {
+        let name = name.to_string();
+        let snake_name = name.to_case(Case::Snake);
+        Field {
+            field_ident: format_ident!("group_{}", snake_name),
+            setter: format_ident!("set_{}", snake_name),
+            getter: format_ident!("get_{}", snake_name),
+            builder: format_ident!("build_{}", snake_name),
+            generic_ident: format_ident!("__Group{}", name.to_case(Case::UpperCamel)),
+            name,
+            default: false,
+            default_expr: None,
+            skip: false,
+            flatten: false,
+            prefix: None,
+            member: None,
+            group: Some(Vec::new()),
+            mode: None,
+            requires: Vec::new(),
+            conflicts_with: Vec::new(),
+            push: None,
+            insert: None,
+            field_trait: None,
+            into: false,
+            required: false,
+            ty: Cow::Owned(syn::parse_quote!(())),
+        }
+    }
+}
+
+fn option_inner(ty: &Type) -> Option<&Type> {
+    let segment = match ty {
+        Type::Path(ty) if ty.qself.is_none() => ty.path.segments.last()?,
+        _ => return None,
+    };
+    match &segment.arguments {
+        PathArguments::AngleBracketed(args)
+            if segment.ident == "Option" && args.args.len() == 1 =>
+        {
+            match &args.args[0] {
+                GenericArgument::Type(ty) => Some(ty),
+                _ => None,
+            }
         }
+        _ => None,
     }
 }
@@ -0,0 +1,138 @@
+use convert_case::{Case, Casing};
+use proc_macro2::{Group, TokenStream, TokenTree};
+use quote::{format_ident, quote};
+use syn::parse::{Parse, ParseStream};
+use syn::{braced, bracketed, parenthesized, Ident, LitInt, Visibility};
+
+/// The input of `__flatten!`: the flattened child's fields, as listed by its state macro,
+/// followed by the parent builder's description, as emitted by the parent's derive.
+pub struct FlattenInput {
+    /// Each field's setter name, and its path of indices from the child: the flattened fields
+    /// it is nested in, and the field itself.
+    fields: Vec<(Vec<LitInt>, String)>,
+    /// The parent's generic parameter holding the child builder.
+    state: Ident,
+    impl_generics: TokenStream,
+    self_ty: TokenStream,
+    where_clause: TokenStream,
+    /// The index of the flattened field in the parent.
+    index: LitInt,
+    prefix: Option<Ident>,
+    /// The visibility of the parent builder, which its lifted setters share.
+    vis: Visibility,
+}
+
+impl Parse for FlattenInput {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let list;
+        bracketed!(list in input);
+        let mut fields = Vec::new();
+        while !list.is_empty() {
+            let field;
+            parenthesized!(field in list);
+            let path;
+            bracketed!(path in field);
+            let mut indices = Vec::new();
+            while !path.is_empty() {
+                indices.push(path.parse()?);
+            }
+            let name = field.parse::<TokenTree>()?.to_string();
+            fields.push((indices, name));
+        }
+        let state;
+        braced!(state in input);
+        let impl_generics;
+        braced!(impl_generics in input);
+        let self_ty;
+        braced!(self_ty in input);
+        let where_clause;
+        braced!(where_clause in input);
+        let index;
+        braced!(index in input);
+        let prefix;
+        braced!(prefix in input);
+        let vis;
+        braced!(vis in input);
+        Ok(FlattenInput {
+            fields,
+            state: state.parse()?,
+            impl_generics: impl_generics.parse()?,
+            self_ty: self_ty.parse()?,
+            where_clause: where_clause.parse()?,
+            index: index.parse()?,
+            prefix: prefix.parse()?,
+            vis: vis.parse()?,
+        })
+    }
+}
+
+impl FlattenInput {
+    pub fn setters(&self) -> TokenStream {
+        let FlattenInput {
+            state,
+            impl_generics,
+            self_ty,
+            where_clause,
+            index,
+            vis,
+            ..
+        } = self;
+        self.fields
+            .iter()
+            .map(|(path, name)| {
+                let name = name.to_case(Case::Snake);
+                let setter = match &self.prefix {
+                    Some(prefix) => format_ident!("set_{}_{}", prefix, name),
+                    None => format_ident!("set_{}", name),
+                };
+                // walk down the nested builders to the field, then map each back up
+                let (last, nested) = path.split_last().unwrap();
+                let mut ty = quote!(#state);
+                let mut bounds = Vec::new();
+                let mut tys = Vec::new();
+                for k in nested {
+                    bounds.push(quote!(#ty: ::builder::MapFlattened<#k>));
+                    tys.push((ty.clone(), k));
+                    ty = quote!(<#ty as ::builder::MapFlattened<#k>>::Field);
+                }
+                bounds.push(quote!(#ty: ::builder::SetField<#last>));
+                let value_ty = quote!(<#ty as ::builder::SetField<#last>>::Value);
+                let mut output = quote!(<#ty as ::builder::SetField<#last>>::Output);
+                let mut body = quote!(<#ty as ::builder::SetField<#last>>::set_field(builder, value));
+                for (ty, k) in tys.into_iter().rev() {
+                    output = quote!(<#ty as ::builder::MapFlattened<#k>>::Output<#output>);
+                    body = quote! {
+                        <#ty as ::builder::MapFlattened<#k>>::map_flattened(builder, |builder| #body)
+                    };
+                }
+                let out_ty = replace_ident(self_ty.clone(), state, &output);
+                quote! {
+                    #[automatically_derived]
+                    impl <#impl_generics> #self_ty #where_clause, #(#bounds),* {
+                        #vis fn #setter(self, value: #value_ty) -> #out_ty {
+                            ::builder::MapFlattened::<#index>::map_flattened(self, |builder| #body)
+                        }
+                    }
+                }
+            })
+            .collect()
+    }
+}
+
+pub fn replace_ident(tokens: TokenStream, ident: &Ident, with: &TokenStream) -> TokenStream {
+    tokens
+        .into_iter()
+        .flat_map(|token| match token {
+            TokenTree::Ident(found) if found == *ident => with.clone(),
+            TokenTree::Group(group) => {
+                let mut new = Group::new(
+                    group.delimiter(),
+                    replace_ident(group.stream(), ident, with),
+                );
+                new.set_span(group.span());
+                TokenTree::Group(new).into()
+            }
+            token => token.into(),
+        })
+        .collect()
+}
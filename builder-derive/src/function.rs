@@ -0,0 +1,518 @@
+use convert_case::{Case, Casing};
+use proc_macro2::{Span, TokenStream};
+use quote::{format_ident, quote, ToTokens};
+use syn::visit_mut::{self, VisitMut};
+use syn::{
+    DeriveInput, FnArg, GenericParam, Generics, Ident, ImplItem, ItemFn, ItemImpl, Lifetime,
+    LifetimeDef, ParenthesizedGenericArguments, Pat, ReturnType, Signature, Type, TypeBareFn,
+    TypeImplTrait, TypeReference, Visibility, WhereClause,
+};
+
+use crate::error::Error;
+use crate::flatten::replace_ident;
+use crate::StructAttrs;
+
+/// Expands `#[builder]` on a function into a builder of its arguments, whose callback runs the
+/// function's body once they are all set.
+pub fn function(item: ItemFn) -> Result<TokenStream, Error> {
+    let ItemFn {
+        attrs,
+        vis,
+        sig,
+        block,
+    } = item;
+    let arguments = Arguments::new(&sig, None)?;
+
+    let mut generics = sig.generics.clone();
+    for (k, lifetime) in arguments.elided.fresh.iter().enumerate() {
+        generics.params.insert(
+            k,
+            GenericParam::Lifetime(LifetimeDef::new(lifetime.clone())),
+        );
+    }
+    let camel = sig.ident.to_string().to_case(Case::UpperCamel);
+    let args_ident = format_ident!("__{}Args", camel);
+    let builder_ident = format_ident!("{}Builder", camel);
+    let args = arguments.builder(&vis, &args_ident, builder_ident, &generics)?;
+
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+    let args_ty = quote!(#args_ident #ty_generics);
+    let output = &arguments.output;
+    let callback_ty = quote!(fn(#args_ty) -> #output);
+    let callback_builder = quote!(<#args_ty as ::builder::BuilderWithCallback<#callback_ty>>);
+    let fn_ident = &sig.ident;
+    let bindings = &arguments.bindings;
+    let marker_binding = marker(&generics).map(|_| quote!(__builder_marker: _,));
+    // the body's statements follow the bindings, as nesting its block would leave braces unused
+    let stmts = &block.stmts;
+    Ok(quote! {
+        #args
+
+        #(#attrs)*
+        #vis fn #fn_ident #impl_generics () -> #callback_builder::CallbackBuilder #where_clause {
+            #callback_builder::builder_with_callback(
+                (|args: #args_ty| -> #output {
+                    let #args_ident { #(#bindings,)* #marker_binding } = args;
+                    #(#stmts)*
+                }) as #callback_ty,
+            )
+        }
+    })
+}
+
+/// Expands `#[builder]` on an inherent `impl` block, adding a `*_builder` method for each of its
+/// methods marked `#[builder]`, whose callback calls the method.
+pub fn methods(mut item: ItemImpl) -> Result<TokenStream, Error> {
+    if let Some((_, path, _)) = &item.trait_ {
+        return Err(syn::Error::new_spanned(
+            path,
+            "`#[builder]` can only be applied to an inherent `impl` block",
+        )
+        .into());
+    }
+    let self_ident = match &*item.self_ty {
+        Type::Path(ty) if ty.qself.is_none() => ty.path.segments.last().unwrap().ident.clone(),
+        ty => return Err(syn::Error::new_spanned(ty, "expected a type name").into()),
+    };
+    let mut builders = Vec::new();
+    let mut builder_fns = Vec::new();
+    for impl_item in &mut item.items {
+        let method = match impl_item {
+            ImplItem::Method(method) => method,
+            _ => continue,
+        };
+        let position = match method
+            .attrs
+            .iter()
+            .position(|attr| attr.path.is_ident("builder"))
+        {
+            Some(position) => position,
+            None => continue,
+        };
+        let attr = method.attrs.remove(position);
+        if !attr.tokens.is_empty() {
+            return Err(syn::Error::new_spanned(
+                attr.tokens,
+                "`#[builder]` on a method takes no arguments",
+            )
+            .into());
+        }
+        let (builder, builder_fn) = method_builder(
+            &item.generics,
+            &item.self_ty,
+            &self_ident,
+            &method.vis,
+            &method.sig,
+        )?;
+        builders.push(builder);
+        builder_fns.push(builder_fn);
+        // the arguments' attributes were for the builder
+        for input in &mut method.sig.inputs {
+            if let FnArg::Typed(arg) = input {
+                arg.attrs.retain(|attr| !attr.path.is_ident("builder"));
+            }
+        }
+    }
+    item.items
+        .extend(builder_fns.into_iter().map(ImplItem::Verbatim));
+    Ok(quote! {
+        #item
+
+        #(#builders)*
+    })
+}
+
+/// The builder of a method's arguments, and the method in the `impl` block returning it.
+fn method_builder(
+    block_generics: &Generics,
+    self_ty: &Type,
+    self_ident: &Ident,
+    vis: &Visibility,
+    sig: &Signature,
+) -> Result<(TokenStream, TokenStream), Error> {
+    let arguments = Arguments::new(sig, Some(self_ty))?;
+
+    // the builder is declared outside of the `impl` block, generic over both its parameters and
+    // the method's, and with `Self` spelled out
+    let self_keyword = format_ident!("Self");
+    let (lifetimes, others): (Vec<_>, Vec<_>) = arguments
+        .elided
+        .fresh
+        .iter()
+        .map(|lifetime| GenericParam::Lifetime(LifetimeDef::new(lifetime.clone())))
+        .chain(block_generics.params.iter().cloned())
+        .chain(sig.generics.params.iter().cloned())
+        .partition(|param| matches!(param, GenericParam::Lifetime(_)));
+    let params = lifetimes.into_iter().chain(others);
+    let mut generics: Generics = syn::parse2(replace_ident(
+        quote!(<#(#params),*>),
+        &self_keyword,
+        &self_ty.to_token_stream(),
+    ))?;
+    let predicates = block_generics
+        .where_clause
+        .iter()
+        .chain(&sig.generics.where_clause)
+        .flat_map(|clause| &clause.predicates)
+        .collect::<Vec<_>>();
+    if !predicates.is_empty() {
+        generics.where_clause = Some(syn::parse2::<WhereClause>(replace_ident(
+            quote!(where #(#predicates),*),
+            &self_keyword,
+            &self_ty.to_token_stream(),
+        ))?);
+    }
+
+    let camel = format!(
+        "{}{}",
+        self_ident,
+        sig.ident.to_string().to_case(Case::UpperCamel)
+    );
+    let args_ident = format_ident!("__{}Args", camel);
+    let call_ident = format_ident!("__{}Call", camel);
+    let builder_ident = format_ident!("{}Builder", camel);
+    let args = arguments.builder(vis, &args_ident, builder_ident, &generics)?;
+
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+    let mut try_generics = generics.clone();
+    try_generics.params.push(syn::parse_quote!(__Error));
+    let (try_impl_generics, _, _) = try_generics.split_for_impl();
+    let args_ty = quote!(#args_ident #ty_generics);
+    let call_ty = quote!(#call_ident #ty_generics);
+    let output = &arguments.output;
+    let marker = marker(&generics);
+    let receiver = arguments.receiver.as_ref();
+    let receiver_field = receiver.map(|receiver| {
+        let ty = &receiver.ty;
+        quote!(receiver: #ty,)
+    });
+    let receiver_arg = receiver.map(|_| quote!(self.receiver,));
+    let idents = &arguments.idents;
+    let method_ident = &sig.ident;
+    // lifetimes are left to inference, since late-bound ones cannot be given explicitly
+    let turbofish = sig
+        .generics
+        .params
+        .iter()
+        .filter_map(|param| match param {
+            GenericParam::Type(param) => Some(&param.ident),
+            GenericParam::Const(param) => Some(&param.ident),
+            GenericParam::Lifetime(_) => None,
+        })
+        .collect::<Vec<_>>();
+    let turbofish = if turbofish.is_empty() {
+        quote!()
+    } else {
+        quote!(::<#(#turbofish),*>)
+    };
+    let builder = quote! {
+        #args
+
+        #[doc(hidden)]
+        #vis struct #call_ident #impl_generics #where_clause {
+            #receiver_field
+            #marker
+        }
+
+        #[automatically_derived]
+        impl #impl_generics ::builder::Callback<#args_ty> for #call_ty #where_clause {
+            type Output = #output;
+
+            fn callback(self, args: #args_ty) -> #output {
+                let #args_ident { #(#idents,)* .. } = args;
+                <#self_ty>::#method_ident #turbofish(#receiver_arg #(#idents),*)
+            }
+        }
+
+        #[automatically_derived]
+        impl #try_impl_generics ::builder::TryCallback<#args_ty, __Error> for #call_ty #where_clause {
+            type Output = ::core::result::Result<#output, __Error>;
+
+            fn try_callback(self, args: ::core::result::Result<#args_ty, __Error>) -> Self::Output {
+                args.map(|args| ::builder::Callback::callback(self, args))
+            }
+        }
+    };
+
+    let mut method_generics = sig.generics.clone();
+    for (k, lifetime) in arguments.elided.fresh.iter().enumerate() {
+        method_generics.params.insert(
+            k,
+            GenericParam::Lifetime(LifetimeDef::new(lifetime.clone())),
+        );
+    }
+    let (method_impl_generics, _, method_where_clause) = method_generics.split_for_impl();
+    let receiver_arg = receiver.map(|receiver| &receiver.arg);
+    let receiver_value = receiver.map(|_| quote!(receiver: self,));
+    let marker_value = marker
+        .as_ref()
+        .map(|_| quote!(__builder_marker: ::core::marker::PhantomData,));
+    let callback_builder = quote!(<#args_ty as ::builder::BuilderWithCallback<#call_ty>>);
+    let builder_fn = format_ident!("{}_builder", sig.ident);
+    let builder_fn = quote! {
+        #vis fn #builder_fn #method_impl_generics (#receiver_arg) -> #callback_builder::CallbackBuilder #method_where_clause {
+            #callback_builder::builder_with_callback(#call_ident { #receiver_value #marker_value })
+        }
+    };
+    Ok((builder, builder_fn))
+}
+
+/// A function's arguments, which become the fields of the struct its builder builds.
+struct Arguments {
+    /// The fields, with the attributes the builder reads.
+    fields: Vec<TokenStream>,
+    /// The fields as the struct declares them.
+    definitions: Vec<TokenStream>,
+    idents: Vec<Ident>,
+    /// The arguments' patterns, which bind them for the function's body.
+    bindings: Vec<Pat>,
+    receiver: Option<SelfArg>,
+    elided: Elided,
+    output: TokenStream,
+}
+
+/// A method's receiver, with its elided lifetime named, and the type the builder stores it as.
+struct SelfArg {
+    arg: TokenStream,
+    ty: Type,
+}
+
+impl Arguments {
+    /// Collects the arguments of `sig`, which is a method of `self_ty` if it is given.
+    fn new(sig: &Signature, self_ty: Option<&Type>) -> Result<Self, Error> {
+        let unsupported = sig
+            .asyncness
+            .map(|token| {
+                syn::Error::new_spanned(token, "`#[builder]` does not support async functions")
+            })
+            .or_else(|| {
+                sig.constness.map(|token| {
+                    syn::Error::new_spanned(token, "`#[builder]` does not support const functions")
+                })
+            })
+            .or_else(|| {
+                sig.unsafety.map(|token| {
+                    syn::Error::new_spanned(token, "`#[builder]` does not support unsafe functions")
+                })
+            })
+            .or_else(|| {
+                sig.variadic.as_ref().map(|variadic| {
+                    syn::Error::new_spanned(
+                        variadic,
+                        "`#[builder]` does not support variadic functions",
+                    )
+                })
+            });
+        if let Some(error) = unsupported {
+            return Err(error.into());
+        }
+        let method_of = |arg: &dyn ToTokens| {
+            self_ty.ok_or_else(|| {
+                syn::Error::new_spanned(arg, "a method needs `#[builder]` on its `impl` block")
+            })
+        };
+        // `Self` means nothing outside of the `impl` block
+        let spell_out = |ty: &Type| -> syn::Result<Type> {
+            match self_ty {
+                Some(self_ty) => syn::parse2(replace_ident(
+                    ty.to_token_stream(),
+                    &format_ident!("Self"),
+                    &self_ty.to_token_stream(),
+                )),
+                None => Ok(ty.clone()),
+            }
+        };
+
+        let mut arguments = Arguments {
+            fields: Vec::new(),
+            definitions: Vec::new(),
+            idents: Vec::new(),
+            bindings: Vec::new(),
+            receiver: None,
+            elided: Elided::default(),
+            output: quote!(()),
+        };
+        // an elided lifetime in the output is the receiver's, if it is a reference
+        let mut receiver_lifetime = None;
+        for input in &sig.inputs {
+            let arg = match input {
+                FnArg::Receiver(receiver) => {
+                    let self_ty = method_of(receiver)?;
+                    let mutability = &receiver.mutability;
+                    arguments.receiver = Some(match &receiver.reference {
+                        Some((_, lifetime)) => {
+                            let lifetime = match lifetime {
+                                Some(lifetime) => lifetime.clone(),
+                                None => arguments.elided.name().unwrap(),
+                            };
+                            arguments.elided.used.push(lifetime.clone());
+                            receiver_lifetime = Some(lifetime.clone());
+                            SelfArg {
+                                arg: quote!(&#lifetime #mutability self),
+                                ty: syn::parse_quote!(&#lifetime #mutability #self_ty),
+                            }
+                        }
+                        None => SelfArg {
+                            arg: quote!(self),
+                            ty: self_ty.clone(),
+                        },
+                    });
+                    continue;
+                }
+                FnArg::Typed(arg) => arg,
+            };
+            let ident = match &*arg.pat {
+                Pat::Ident(pat) if pat.subpat.is_none() => &pat.ident,
+                pat => return Err(syn::Error::new_spanned(pat, "expected an identifier").into()),
+            };
+            let mut ty = (*arg.ty).clone();
+            arguments.elided.visit_type_mut(&mut ty);
+            if ident == "self" {
+                method_of(arg)?;
+                arguments.receiver = Some(SelfArg {
+                    arg: quote!(self: #ty),
+                    ty: spell_out(&ty)?,
+                });
+                continue;
+            }
+            let ty = spell_out(&ty)?;
+            // setters are named after the arguments, unless the argument says otherwise
+            let attrs = &arg.attrs;
+            arguments
+                .fields
+                .push(quote!(#[builder(setter = #ident)] #(#attrs)* #ident: #ty));
+            let attrs = attrs.iter().filter(|attr| !attr.path.is_ident("builder"));
+            arguments.definitions.push(quote!(#(#attrs)* #ident: #ty));
+            arguments.idents.push(ident.clone());
+            arguments.bindings.push((*arg.pat).clone());
+        }
+        if let Some(error) = arguments.elided.error.take() {
+            return Err(error.into());
+        }
+
+        if let ReturnType::Type(_, ty) = &sig.output {
+            // otherwise it is the inputs' only lifetime, if they have one
+            let mut ty = spell_out(ty)?;
+            let elided = &mut arguments.elided;
+            elided.output = true;
+            elided.single = receiver_lifetime.or_else(|| match &elided.used[..] {
+                [lifetime] => Some(lifetime.clone()),
+                _ => None,
+            });
+            elided.visit_type_mut(&mut ty);
+            if let Some(error) = elided.error.take() {
+                return Err(error.into());
+            }
+            arguments.output = quote!(#ty);
+        }
+        Ok(arguments)
+    }
+
+    /// The struct of the arguments, and its builder.
+    fn builder(
+        &self,
+        vis: &Visibility,
+        args_ident: &Ident,
+        builder_ident: Ident,
+        generics: &Generics,
+    ) -> Result<TokenStream, Error> {
+        let marker = marker(generics);
+        let skip_marker = marker
+            .as_ref()
+            .map(|marker| quote!(#[builder(skip)] #marker));
+        let fields = &self.fields;
+        let where_clause = &generics.where_clause;
+        let input: DeriveInput = syn::parse_quote! {
+            #vis struct #args_ident #generics #where_clause {
+                #(#fields,)*
+                #skip_marker
+            }
+        };
+        let mut builder = StructAttrs::try_from(&input)?;
+        builder.builder_ident = builder_ident;
+        builder.build_fn = syn::parse_quote!(call);
+        let builder_with_callback = builder.builder_with_callback();
+
+        let (impl_generics, _, where_clause) = generics.split_for_impl();
+        let definitions = &self.definitions;
+        Ok(quote! {
+            #[doc(hidden)]
+            #vis struct #args_ident #impl_generics #where_clause {
+                #(#definitions,)*
+                #marker
+            }
+
+            #builder_with_callback
+
+            #builder
+        })
+    }
+}
+
+/// A field using every lifetime and type parameter, which the generated structs may not.
+fn marker(generics: &Generics) -> Option<TokenStream> {
+    if generics.lifetimes().next().is_none() && generics.type_params().next().is_none() {
+        return None;
+    }
+    let lifetimes = generics.lifetimes().map(|param| &param.lifetime);
+    let types = generics.type_params().map(|param| &param.ident);
+    Some(quote! {
+        __builder_marker: ::core::marker::PhantomData<fn(#(&#lifetimes (),)*) -> (#(#types,)*)>
+    })
+}
+
+/// Names the lifetimes a signature leaves elided, since the arguments struct has to declare them.
+#[derive(Default)]
+struct Elided {
+    /// The lifetimes introduced for the elided ones in the inputs.
+    fresh: Vec<Lifetime>,
+    /// Every lifetime the inputs use.
+    used: Vec<Lifetime>,
+    /// Whether the output is being visited, which takes `single` rather than fresh lifetimes.
+    output: bool,
+    single: Option<Lifetime>,
+    error: Option<syn::Error>,
+}
+
+impl Elided {
+    fn name(&mut self) -> Option<Lifetime> {
+        if self.output {
+            return self.single.clone();
+        }
+        let lifetime = Lifetime::new(&format!("'__elided{}", self.fresh.len()), Span::call_site());
+        self.fresh.push(lifetime.clone());
+        Some(lifetime)
+    }
+}
+
+impl VisitMut for Elided {
+    fn visit_lifetime_mut(&mut self, lifetime: &mut Lifetime) {
+        if lifetime.ident == "_" {
+            if let Some(name) = self.name() {
+                *lifetime = name;
+            }
+        }
+        if !self.output && !self.used.contains(lifetime) {
+            self.used.push(lifetime.clone());
+        }
+    }
+
+    fn visit_type_reference_mut(&mut self, reference: &mut TypeReference) {
+        if reference.lifetime.is_none() {
+            reference.lifetime = self.name();
+        }
+        visit_mut::visit_type_reference_mut(self, reference);
+    }
+
+    // lifetimes elided in `fn(&T)` and `Fn(&T)` are higher-ranked, and stay elided
+    fn visit_type_bare_fn_mut(&mut self, _: &mut TypeBareFn) {}
+
+    fn visit_parenthesized_generic_arguments_mut(&mut self, _: &mut ParenthesizedGenericArguments) {
+    }
+
+    fn visit_type_impl_trait_mut(&mut self, ty: &mut TypeImplTrait) {
+        self.error.get_or_insert_with(|| {
+            syn::Error::new_spanned(ty, "`#[builder]` does not support `impl Trait` types")
+        });
+    }
+}
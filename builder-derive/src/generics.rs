@@ -1,52 +1,24 @@
-use convert_case::{Case, Casing};
 use proc_macro2::TokenStream;
-use quote::{format_ident, quote, ToTokens};
-use syn::{ConstParam, Ident, LifetimeDef, TypeParam, WherePredicate};
+use quote::{quote, ToTokens};
+use syn::{ConstParam, GenericParam, LifetimeDef, TypeParam, WherePredicate};
 
 pub struct Generics<'a> {
+    params: Vec<&'a GenericParam>,
     lifetimes: Vec<&'a LifetimeDef>,
-    types: Vec<Generic<'a>>,
+    types: Vec<&'a TypeParam>,
     consts: Vec<&'a ConstParam>,
     where_predicates: Vec<&'a WherePredicate>,
 }
 
 impl<'a> Generics<'a> {
-    pub fn field_definitions(&'a self) -> impl Iterator<Item = TokenStream> + 'a {
-        self.types.iter().map(
-            |Generic {
-                 field_name,
-                 field_type,
-                 ..
-             }| quote!(#field_name: #field_type),
-        )
-    }
-
-    pub fn default_constructors(&'a self) -> impl Iterator<Item = TokenStream> + 'a {
-        self.types
-            .iter()
-            .map(|Generic { field_name, .. }| quote!(#field_name: ::core::marker::PhantomData))
-    }
-
     pub fn impl_generics(
         &'a self,
         other_types: impl IntoIterator<Item = TokenStream> + 'a,
     ) -> impl Iterator<Item = TokenStream> + 'a {
-        let ty_generics = self.types.iter().map(Generic::impl_generic);
-        let const_generics = self.consts.iter().map(
-            |syn::ConstParam {
-                 attrs,
-                 const_token,
-                 ident,
-                 colon_token,
-                 ty,
-                 ..
-             }| quote!(#(#attrs)* #const_token #ident #colon_token #ty),
-        );
-
         self.lifetimes()
-            .chain(ty_generics)
+            .chain(self.types())
             .chain(other_types)
-            .chain(const_generics)
+            .chain(self.consts())
     }
 
     pub fn ty_generics(
@@ -57,10 +29,7 @@ impl<'a> Generics<'a> {
             .lifetimes
             .iter()
             .map(|param| param.lifetime.to_token_stream());
-        let ty_generics = self
-            .types
-            .iter()
-            .map(|param| param.param.ident.to_token_stream());
+        let ty_generics = self.types.iter().map(|param| param.ident.to_token_stream());
         let const_generics = self
             .consts
             .iter()
@@ -76,12 +45,37 @@ impl<'a> Generics<'a> {
         self.lifetimes.iter().map(ToTokens::to_token_stream)
     }
 
+    /// The type parameters without their defaults, which are only allowed trailing and so
+    /// cannot precede the builder's own parameters.
     pub fn types(&'a self) -> impl Iterator<Item = TokenStream> + 'a {
-        self.types.iter().map(|ty| ty.param.to_token_stream())
+        self.types.iter().map(
+            |TypeParam {
+                 attrs,
+                 ident,
+                 colon_token,
+                 bounds,
+                 ..
+             }| quote!(#(#attrs)* #ident #colon_token #bounds),
+        )
     }
 
+    /// The const parameters without their defaults.
     pub fn consts(&'a self) -> impl Iterator<Item = TokenStream> + 'a {
-        self.consts.iter().map(ToTokens::to_token_stream)
+        self.consts.iter().map(
+            |ConstParam {
+                 attrs,
+                 const_token,
+                 ident,
+                 colon_token,
+                 ty,
+                 ..
+             }| quote!(#(#attrs)* #const_token #ident #colon_token #ty),
+        )
+    }
+
+    /// The generic parameters in the order they were declared, rather than grouped by kind.
+    pub fn params(&'a self) -> &'a [&'a GenericParam] {
+        &self.params
     }
 
     pub fn where_predicates(&'a self) -> &'a [&'a WherePredicate] {
@@ -92,8 +86,9 @@ impl<'a> Generics<'a> {
 impl<'a> From<&'a syn::Generics> for Generics<'a> {
     fn from(generics: &'a syn::Generics) -> Self {
         Generics {
+            params: generics.params.iter().collect(),
             lifetimes: generics.lifetimes().collect(),
-            types: generics.type_params().map(Generic::from).collect(),
+            types: generics.type_params().collect(),
             consts: generics.const_params().collect(),
             where_predicates: generics
                 .where_clause
@@ -105,33 +100,3 @@ impl<'a> From<&'a syn::Generics> for Generics<'a> {
         }
     }
 }
-
-pub struct Generic<'a> {
-    param: &'a TypeParam,
-    field_type: TokenStream,
-    field_name: Ident,
-}
-
-impl<'a> Generic<'a> {
-    fn impl_generic(&self) -> TokenStream {
-        let TypeParam {
-            attrs,
-            ident,
-            colon_token,
-            bounds,
-            ..
-        } = self.param;
-        quote!(#(#attrs)* #ident #colon_token #bounds)
-    }
-}
-
-impl<'a> From<&'a syn::TypeParam> for Generic<'a> {
-    fn from(param: &'a TypeParam) -> Self {
-        let ident = &param.ident;
-        Generic {
-            param,
-            field_type: quote!(::core::marker::PhantomData<#ident>),
-            field_name: format_ident!("_generic_{}", ident.to_string().to_case(Case::Snake)),
-        }
-    }
-}
@@ -1,21 +1,104 @@
 use std::iter::{empty, once};
+use std::sync::atomic::{AtomicUsize, Ordering};
 
-use proc_macro2::TokenStream;
+use proc_macro2::{Literal, TokenStream};
 use quote::{quote, ToTokens};
-use syn::{DeriveInput, Ident, Visibility};
+use syn::{DeriveInput, GenericParam, Ident, Visibility};
 
+mod attrs;
 mod error;
 mod fields;
+mod flatten;
+mod function;
 mod generics;
+mod state;
 
+use convert_case::{Case, Casing};
 use error::Error;
-use fields::{Field, Fields};
+use fields::{Field, FieldTrait, Fields, GroupMode, Relation, RelationKind, Shape};
 use generics::Generics;
 
+/// Derives a typestate builder for a struct.
+///
+/// # Naming partially-built states
+///
+/// `FooBuilder![a, b]` names the builder with the fields `a` and `b` set, and
+/// `FooBuilder![<T> a; C]` one for `Foo<T>` with the callback `C`. The macro has the struct's
+/// visibility, so a public struct's can be used from other crates. It names the builder type
+/// where it is used, so import both with `use other::FooBuilder`.
+///
+/// # Flattening
+///
+/// `#[builder(flatten)]` lifts the setters of a field's own builder into the parent, prefixed with
+/// `prefix = name` if given, including those it lifted from its own flattened fields. The field
+/// list is found through `FooBuilder` next to the field's type `Foo`, so the type can come from
+/// another module or crate if its builder is imported with it, or renamed if its builder is
+/// renamed to match (`use other::{Foo as Bar, FooBuilder as BarBuilder}`), but not a type alias.
+///
+/// # Field traits
+///
+/// `#[builder(field_trait)]` on a field `x` defines the traits `SetX` and `HasX` next to the
+/// struct, and implements them for its builder, so code can set or read `x` on any builder with
+/// it. Another struct with the same field reuses the traits with `field_trait = path`, the module
+/// they are defined in (`self` if it is the same one), rather than defining them again, which
+/// would conflict.
+///
+/// # Computed defaults
+///
+/// `#[builder(default = |b| ...)]` and `#[builder(skip = |b| ...)]` compute a field from the
+/// final values of others, read as `b.field`. They are read through a shared reference, as in a
+/// `&self` method, so take an owned value with `b.field.clone()`. Where `b` is bound again, by
+/// a closure's parameters or a pattern, `b.field` reads the new binding, except in the input of
+/// a macro, which is only seen as tokens.
+///
+/// # Validation
+///
+/// With `#[builder(validate)]`, the built value is checked by its `Validate` impl, and the
+/// builder only has `try_build`, returning the error: there is no `build`, `Build` impl or
+/// `From<FooBuilder>` which could skip the check, so the struct cannot be flattened either. A
+/// nested builder's `try_build` stores its error in the parent, so `build_x().try_build()` under
+/// the outermost `try_build` returns it from there.
+///
+/// `try_build` on a builder with `requires` relations returns a `RequiresError` when one is
+/// broken, so its error type must implement `From<RequiresError>`, as `String` does.
+///
+/// # Steps
+///
+/// `#[builder(steps)]` makes the required fields, and field groups, be set in declaration order,
+/// then the optional ones. Each step has its own builder state, holding the fields it has not
+/// reached as `Locked`, so only the next setter is found, followed by the optional setters and
+/// `build` once every required field is set.
+///
+/// # Remote types
+///
+/// `#[builder(remote = "other::Type")]` on a mirror of a foreign struct derives a builder for
+/// it, with `Mirror::builder()` and `Mirror::builder_with_callback(..)`. The orphan rule keeps the
+/// foreign type from implementing `Builder`, so a parent holding it has no `build_x()` and
+/// cannot flatten it: build it with the mirror, passing it on with
+/// `Mirror::builder_with_callback(|x| parent.set_x(x))`.
+///
+/// # `#[non_exhaustive]` structs
+///
+/// A `#[non_exhaustive]` struct can only be built through its builder outside its crate, so the
+/// derive keeps the builder compatible as fields are added:
+///
+/// - every field needs a default (`default`, `default = ...`, `skip`, `push`, `insert` or
+///   `flatten`), or must be marked `#[builder(required)]`;
+/// - `required` is only for fields present when the struct was published, as adding a field
+///   without a default is a breaking change;
+/// - adding a field with a default, or a member to an existing field group, is not a breaking
+///   change, as long as users don't name the builder's generic arguments, which change with
+///   every field.
+///
+/// Removing or renaming a field, adding a `requires` or `conflicts_with` relation, or adding a
+/// field group, remain breaking changes.
 #[proc_macro_derive(Builder, attributes(builder))]
 pub fn derive(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
     let input = syn::parse_macro_input!(input as DeriveInput);
-    let builder = StructAttrs::try_from(&input).unwrap();
+    let builder = match StructAttrs::try_from(&input) {
+        Ok(builder) => builder,
+        Err(error) => return error.to_compile_error().into(),
+    };
     let builder_with_callback = builder.builder_with_callback();
     quote! {
         #builder_with_callback
@@ -25,6 +108,58 @@ pub fn derive(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
     .into()
 }
 
+#[proc_macro_attribute]
+pub fn builder(
+    attr: proc_macro::TokenStream,
+    item: proc_macro::TokenStream,
+) -> proc_macro::TokenStream {
+    if !attr.is_empty() {
+        return syn::Error::new(
+            proc_macro2::Span::call_site(),
+            "`#[builder]` takes no arguments",
+        )
+        .to_compile_error()
+        .into();
+    }
+    let expanded = match syn::parse_macro_input!(item as syn::Item) {
+        syn::Item::Fn(item) => function::function(item),
+        syn::Item::Impl(item) => function::methods(item),
+        item => Err(syn::Error::new_spanned(
+            item,
+            "`#[builder]` can only be applied to a function or an `impl` block",
+        )
+        .into()),
+    };
+    match expanded {
+        Ok(tokens) => tokens.into(),
+        Err(error) => error.to_compile_error().into(),
+    }
+}
+
+#[doc(hidden)]
+#[proc_macro]
+pub fn __flatten(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
+    syn::parse_macro_input!(input as flatten::FlattenInput)
+        .setters()
+        .into()
+}
+
+#[doc(hidden)]
+#[proc_macro]
+pub fn __state(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
+    syn::parse_macro_input!(input as state::StateInput)
+        .builder()
+        .into()
+}
+
+#[doc(hidden)]
+#[proc_macro]
+pub fn __state_macro(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
+    syn::parse_macro_input!(input as state::StateMacroInput)
+        .state_macro()
+        .into()
+}
+
 struct StructAttrs<'a> {
     vis: &'a Visibility,
     ident: &'a Ident,
@@ -32,6 +167,17 @@ struct StructAttrs<'a> {
     callback: Ident,
     generics: Generics<'a>,
     fields: Fields<'a>,
+    default_order: Vec<usize>,
+    relations: Vec<Relation>,
+    /// The required slots of a `steps` builder, in the order they must be set.
+    steps: Option<Vec<usize>>,
+    validate: bool,
+    /// The type this struct mirrors, which the builder builds in its place.
+    remote: Option<syn::Path>,
+    /// The name of the inherent method finishing the builder, `build` unless it wraps a function.
+    build_fn: Ident,
+    /// The name of the `FooBuilder![...]` macro before it is re-exported, unique to the struct.
+    macro_ident: Ident,
 }
 
 impl<'a> StructAttrs<'a> {
@@ -60,15 +206,151 @@ impl<'a> StructAttrs<'a> {
             .ty_generics(once(self.callback.to_token_stream()).chain(other_types))
     }
 
+    /// The path of the type the builder builds: the struct itself, or the type it mirrors.
+    fn built_path(&self) -> TokenStream {
+        match &self.remote {
+            Some(remote) => remote.to_token_stream(),
+            None => self.ident.to_token_stream(),
+        }
+    }
+
+    fn built_ty(&self) -> TokenStream {
+        let path = self.built_path();
+        let ty_generics = self.generics.ty_generics(empty());
+        quote!(#path <#(#ty_generics),*>)
+    }
+
     fn where_clause(&'a self) -> TokenStream {
         let where_predicates = self.generics.where_predicates();
-        let built_type = self.ident;
-        let ty_generics = self.generics.ty_generics(empty());
+        let built_ty = self.built_ty();
         let callback = &self.callback;
-        quote!(where #(#where_predicates,)* #callback: ::builder::Callback<#built_type <#(#ty_generics,)*>>)
+        let flattened = self
+            .fields
+            .fields()
+            .filter(|field| field.flatten)
+            .map(|field| {
+                let ty = &field.ty;
+                quote!(#ty: ::builder::Builder)
+            });
+        quote!(where #(#where_predicates,)* #(#flattened,)* #callback: ::builder::Callback<#built_ty>)
+    }
+
+    /// The struct's own `where` clause, for the builder and the structs holding it, which would
+    /// otherwise name types such as `T::Item` without the bounds that make them valid.
+    fn struct_where_clause(&self) -> TokenStream {
+        let where_predicates = self.generics.where_predicates();
+        quote!(where #(#where_predicates,)*)
+    }
+
+    /// The position of field `i` among the required slots of a `steps` builder, if it is one.
+    fn step(&self, i: usize) -> Option<usize> {
+        self.steps.as_ref()?.iter().position(|&step| step == i)
+    }
+
+    /// Whether field `j` of a `steps` builder is still locked while field `i` is set: the
+    /// required slots after `i` are, and every optional field until the last slot is set.
+    fn locked(&self, i: usize, j: usize) -> bool {
+        match (self.step(i), self.step(j)) {
+            (Some(p), Some(q)) => q > p,
+            (Some(_), None) => true,
+            (None, _) => false,
+        }
+    }
+
+    /// Whether setting field `i` of a `steps` builder unlocks field `j`: the next required slot,
+    /// or every optional field once the last slot is set.
+    fn unlocks(&self, i: usize, j: usize) -> bool {
+        let last = self.steps.as_ref().map_or(0, Vec::len);
+        match (self.step(i), self.step(j)) {
+            (Some(p), Some(q)) => q == p + 1,
+            (Some(p), None) => p + 1 == last,
+            (None, _) => false,
+        }
+    }
+
+    /// The state field `j` is pinned to while field `i` is set, if any. Only a `steps` builder
+    /// pins fields: those it has not reached are `Locked`, so each step is its own type, with
+    /// only the next setter.
+    fn pinned(&self, i: usize, j: usize) -> Option<TokenStream> {
+        let ty = &self.fields.fields().nth(j)?.ty;
+        self.locked(i, j).then(|| quote!(::builder::Locked<#ty>))
+    }
+
+    /// The generic parameters of the fields not pinned while field `i` is set, optionally
+    /// including field `i`'s own.
+    fn free_generics(&'a self, i: usize, own: bool) -> impl Iterator<Item = TokenStream> + 'a {
+        self.fields
+            .fields()
+            .enumerate()
+            .filter(move |&(j, _)| {
+                if j == i {
+                    own
+                } else {
+                    self.pinned(i, j).is_none()
+                }
+            })
+            .map(|(_, field)| field.generic_ident.to_token_stream())
+    }
+
+    /// The states of the fields, with field `i` in `state` and the others generic or pinned.
+    fn states_with(
+        &'a self,
+        i: usize,
+        state: TokenStream,
+    ) -> impl Iterator<Item = TokenStream> + 'a {
+        self.fields.fields().enumerate().map(move |(j, field)| {
+            if j == i {
+                state.clone()
+            } else {
+                self.pinned(i, j)
+                    .unwrap_or_else(|| field.generic_ident.to_token_stream())
+            }
+        })
+    }
+
+    /// The states of the fields once field `i` is set to `state`, with the fields it unlocks
+    /// unset.
+    fn states_after(
+        &'a self,
+        i: usize,
+        state: TokenStream,
+    ) -> impl Iterator<Item = TokenStream> + 'a {
+        let fields = self.fields.fields();
+        self.states_with(i, state)
+            .zip(fields)
+            .enumerate()
+            .map(move |(j, (state, field))| {
+                if self.unlocks(i, j) {
+                    let ty = &field.ty;
+                    quote!(::builder::NoData<#ty>)
+                } else {
+                    state
+                }
+            })
+    }
+
+    /// The states of the fields of a new builder: unset, except those a `steps` builder locks
+    /// until its first required slot is set.
+    fn initial_states(&'a self) -> impl Iterator<Item = TokenStream> + 'a {
+        let first = self.steps.as_ref().and_then(|steps| steps.first().copied());
+        let fields = self.fields.fields();
+        self.fields
+            .no_data_generics()
+            .zip(fields)
+            .enumerate()
+            .map(move |(j, (state, field))| match first {
+                Some(first) if self.locked(first, j) => {
+                    let ty = &field.ty;
+                    quote!(::builder::Locked<#ty>)
+                }
+                _ => state,
+            })
     }
 
     fn builder_with_callback(&self) -> TokenStream {
+        if self.remote.is_some() {
+            return self.remote_constructors();
+        }
         let callback = &self.callback;
         let impl_generics = self.impl_generics(empty());
         let ident = &self.ident;
@@ -76,7 +358,7 @@ impl<'a> StructAttrs<'a> {
         let where_clause = self.where_clause();
 
         let builder_ident = &self.builder_ident;
-        let builder_generics = self.ty_generics(self.fields.no_data_generics());
+        let builder_generics = self.ty_generics(self.initial_states());
         quote! {
             #[automatically_derived]
             impl <#(#impl_generics,)*> builder::BuilderWithCallback<#callback> for #ident <#(#ty_generics,)*> #where_clause
@@ -90,21 +372,79 @@ impl<'a> StructAttrs<'a> {
         }
     }
 
+    /// A foreign type cannot implement `BuilderWithCallback`, so its mirror provides the
+    /// constructors, along with a check that it has exactly the foreign type's fields.
+    fn remote_constructors(&self) -> TokenStream {
+        let vis = self.vis;
+        let callback = &self.callback;
+        let impl_generics = self.generics.impl_generics(empty());
+        let ident = self.ident;
+        let ty_generics = self.generics.ty_generics(empty());
+        let where_predicates = self.generics.where_predicates();
+        let built_ty = self.built_ty();
+
+        let builder_ident = &self.builder_ident;
+        let builder_generics = self
+            .generics
+            .ty_generics(once(quote!(fn(#built_ty) -> #built_ty)).chain(self.initial_states()));
+        let callback_builder_generics = self.ty_generics(self.initial_states());
+        let locals = |field: &Field| field.field_ident.to_token_stream();
+        let remote = self.construct(&self.built_path(), locals);
+        let mirror = self.construct(&quote!(Self), locals);
+        quote! {
+            #[automatically_derived]
+            impl <#(#impl_generics),*> #ident <#(#ty_generics),*>
+            where
+                #(#where_predicates,)*
+            {
+                #vis fn builder() -> #builder_ident <#(#builder_generics),*> {
+                    #builder_ident::new((|this| this) as fn(#built_ty) -> #built_ty)
+                }
+
+                #vis fn builder_with_callback<#callback: ::builder::Callback<#built_ty>>(
+                    callback: #callback,
+                ) -> #builder_ident <#(#callback_builder_generics),*> {
+                    #builder_ident::new(callback)
+                }
+
+                #[doc(hidden)]
+                #[allow(dead_code)]
+                fn __remote(remote: #built_ty) -> #built_ty {
+                    let #remote = remote;
+                    let mirror = #mirror;
+                    let #mirror = mirror;
+                    #remote
+                }
+            }
+        }
+    }
+
     fn default_constructor(&self) -> TokenStream {
         let callback = &self.callback;
         let impl_generics = self.impl_generics(empty());
         let builder_ident = &self.builder_ident;
-        let ty_generics = self.ty_generics(self.fields.no_data_generics());
+        let ty_generics = self.ty_generics(self.initial_states());
         let where_clause = self.where_clause();
 
-        let generic_fields = self.generics.default_constructors();
-        let fields = self.fields.default_constructors();
+        let first = self.steps.as_ref().and_then(|steps| steps.first().copied());
+        let fields = self
+            .fields
+            .default_constructors()
+            .zip(self.fields.fields())
+            .enumerate()
+            .map(|(j, (constructor, field))| match first {
+                Some(first) if self.locked(first, j) => {
+                    let field_ident = &field.field_ident;
+                    quote!(#field_ident: ::builder::Locked::new())
+                }
+                _ => constructor,
+            });
         quote! {
             #[automatically_derived]
             impl <#(#impl_generics),*> #builder_ident <#(#ty_generics),*> #where_clause {
                 fn new(callback: #callback) -> Self {
                     Self {
-                        #(#generic_fields,)*
+                        _built: ::core::marker::PhantomData,
                         callback,
                         #(#fields,)*
                     }
@@ -114,51 +454,34 @@ impl<'a> StructAttrs<'a> {
     }
 
     fn setters(&'a self) -> impl Iterator<Item = TokenStream> + 'a {
-        self.fields.fields().enumerate().map(|(i, field)| {
-            let impl_generics = self.impl_generics(
-                self.fields
-                    .fields_except(
-                        i,
-                        |field| Some(field.generic_ident.to_token_stream()),
-                        |_| None,
-                    )
-                    .flatten(),
-            );
+        // a flattened field is set through the setters lifted from its builder, and a field group
+        // through the setters of its members
+        let fields = self.fields.fields().enumerate();
+        fields.filter(|(_, field)| !field.flatten && field.group.is_none()).map(|(i, field)| {
+            let impl_generics = self.impl_generics(self.free_generics(i, false));
             let impl_generics = quote!(<#(#impl_generics),*>);
             let builder_ident = &self.builder_ident;
-            let in_ty_generics = self.ty_generics(self.fields.fields_except(
-                i,
-                |field| field.generic_ident.to_token_stream(),
-                |Field { ty, .. }| quote!(::builder::NoData<#ty>),
-            ));
+            let ty = &field.ty;
+            let in_ty_generics = self.ty_generics(self.states_with(i, quote!(::builder::NoData<#ty>)));
             let in_ty_generics = quote!(<#(#in_ty_generics),*>);
-            let callback_ty_generics = self.ty_generics(self.fields.fields_except(
-                    i,
-                    |field| Some(field.generic_ident.to_token_stream()),
-                    |_| None,
-            ).flatten());
+            let callback_ty_generics = self.ty_generics(self.free_generics(i, false));
             let where_clause = self.where_clause();
+            let struct_where_clause = self.struct_where_clause();
+            let conflicts = self.conflicts(i);
 
             let setter = &field.setter;
             let builder_fn = &field.builder;
-            let ty = field.ty;
-            let out_ty_generics = self.ty_generics(self.fields.fields_except(
-                i,
-                |field| field.generic_ident.to_token_stream(),
-                |_| ty.to_token_stream(),
-            ));
+            let out_ty_generics = self.ty_generics(self.states_after(i, ty.to_token_stream()));
 
-            let generic_fields = self.generics.default_constructors();
-            let fields = self.fields.fields().enumerate().map(|(j, field)| {
-                let field_ident = &field.field_ident;
-                if i == j {
-                    // this is the field we are writing the impl for; fill in `value`
-                    quote!(#field_ident: value)
-                } else {
-                    // otherwise propagate self value
-                    quote!(#field_ident: self.#field_ident)
-                }
-            });
+            let with_value = self.with_field(i, quote!(self), quote!(value));
+
+            // a fallible nested builder stores its result, to be unwrapped by `try_build`
+            let try_impl_generics =
+                self.impl_generics(self.free_generics(i, false).chain(once(quote!(__Error))));
+            let try_impl_generics = quote!(<#(#try_impl_generics),*>);
+            let try_out_ty_generics = self.ty_generics(self.states_after(i, quote!(::core::result::Result<#ty, __Error>)));
+            let try_out_ty = quote!(#builder_ident <#(#try_out_ty_generics),*>);
+            let with_result = self.with_field(i, quote!(parent), quote!(val));
 
             let in_ty = quote!(#builder_ident #in_ty_generics);
             let impl_line = quote! {
@@ -167,36 +490,67 @@ impl<'a> StructAttrs<'a> {
             };
             let out_ty = quote!(#builder_ident <#(#out_ty_generics),*>);
 
+            let index = Literal::usize_unsuffixed(i);
+
             let callback_ident = quote::format_ident!("__{}{}", builder_ident, field.generic_ident);
             let callback_def = quote!(#callback_ident #impl_generics);
             let callback_use = quote!(#callback_ident <#(#callback_ty_generics),*>);
 
+            let (value_ty, into_value) = if field.into {
+                (
+                    quote!(impl ::core::convert::Into<#ty>),
+                    quote!(let value = ::core::convert::Into::into(value);),
+                )
+            } else {
+                (ty.to_token_stream(), quote!())
+            };
+
+            let vis = self.vis;
             quote! {
                 #impl_line #where_clause {
-                    fn #setter(self, value: #ty) -> #out_ty {
-                        #builder_ident {
-                            #(#generic_fields,)*
-                            callback: self.callback,
-                            #(#fields,)*
-                        }
+                    #vis fn #setter(self, value: #value_ty) -> #out_ty
+                    where
+                        #(#conflicts,)*
+                    {
+                        #into_value
+                        #with_value
                     }
                 }
 
+                #[automatically_derived]
+                impl #impl_generics ::builder::SetField<#index> for #in_ty #where_clause #(, #conflicts)* {
+                    type Value = #ty;
+                    type Output = #out_ty;
+
+                    fn set_field(self, value: #ty) -> #out_ty {
+                        self.#setter(value)
+                    }
+                }
+
+                #[doc(hidden)]
                 #[automatically_derived]
                 #[allow(non_camel_case_types)]
-                struct #callback_def {
+                #vis struct #callback_def #struct_where_clause {
                     parent: #in_ty,
                 }
                 #[automatically_derived]
-                impl #impl_generics ::builder::Callback<#ty> for #callback_use #where_clause {
+                impl #impl_generics ::builder::Callback<#ty> for #callback_use #where_clause #(, #conflicts)* {
                     type Output = #out_ty;
                     fn callback(self, val: #ty) -> Self::Output {
                         self.parent.#setter(val)
                     }
                 }
+                #[automatically_derived]
+                impl #try_impl_generics ::builder::TryCallback<#ty, __Error> for #callback_use #where_clause #(, #conflicts)* {
+                    type Output = #try_out_ty;
+                    fn try_callback(self, val: ::core::result::Result<#ty, __Error>) -> Self::Output {
+                        let parent = self.parent;
+                        #with_result
+                    }
+                }
 
-                #impl_line #where_clause, #ty: ::builder::BuilderWithCallback<#callback_use> {
-                    fn #builder_fn(self) -> <#ty as ::builder::BuilderWithCallback<#callback_use>>::CallbackBuilder {
+                #impl_line #where_clause #(, #conflicts)*, #ty: ::builder::BuilderWithCallback<#callback_use> {
+                    #vis fn #builder_fn(self) -> <#ty as ::builder::BuilderWithCallback<#callback_use>>::CallbackBuilder {
                         <#ty as ::builder::BuilderWithCallback<#callback_use>>::builder_with_callback(#callback_ident { parent: self })
                     }
                 }
@@ -204,76 +558,919 @@ impl<'a> StructAttrs<'a> {
         })
     }
 
-    fn build(&self) -> TokenStream {
-        let callback = &self.callback;
-        let impl_generics = self.impl_generics(self.fields.fields().filter_map(|field| {
+    fn group_setters(&'a self) -> impl Iterator<Item = TokenStream> + 'a {
+        let fields = self.fields.fields().enumerate();
+        fields.filter_map(move |(i, field)| {
+            let members = field.group.as_ref()?;
+            let impl_generics = self
+                .impl_generics(self.free_generics(i, false))
+                .collect::<Vec<_>>();
+            let builder_ident = &self.builder_ident;
+            let ty = &field.ty;
+            let in_ty_generics = self
+                .ty_generics(self.states_with(i, quote!(::builder::NoData<#ty>)))
+                .collect::<Vec<_>>();
+            let out_ty_generics = self
+                .ty_generics(self.states_after(i, ty.to_token_stream()))
+                .collect::<Vec<_>>();
+            let where_clause = self.where_clause();
+            let conflicts = self.conflicts(i);
+            let where_clause = quote!(#where_clause #(, #conflicts)*);
+            let vis = self.vis;
+            // once a member is set, the group is complete and no member can be set again
+            let setters = members.iter().enumerate().map(|(k, member)| {
+                let setter = &member.setter;
+                let member_ty = member.ty;
+                let values = (0..members.len()).map(|j| {
+                    if j == k {
+                        quote!(::core::option::Option::Some(value))
+                    } else {
+                        quote!(::core::option::Option::None)
+                    }
+                });
+                let with_value = self.with_field(i, quote!(self), quote!((#(#values,)*)));
+                quote! {
+                    #vis fn #setter(self, value: #member_ty) -> #builder_ident <#(#out_ty_generics),*> {
+                        #with_value
+                    }
+                }
+            });
+            // while further members of an `at_least_one` group can be set once it is complete
+            let more_setters = members.iter().enumerate().map(|(k, member)| {
+                let setter = &member.setter;
+                let member_ty = member.ty;
+                let field_ident = &field.field_ident;
+                let k = Literal::usize_unsuffixed(k);
+                quote! {
+                    #vis fn #setter(mut self, value: #member_ty) -> Self {
+                        self.#field_ident.#k = ::core::option::Option::Some(value);
+                        self
+                    }
+                }
+            });
+            let more_setters = if field.mode == Some(GroupMode::AtLeastOne) {
+                // whichever fields a `steps` builder has reached since
+                let set_impl_generics = self.impl_generics(
+                    self.fields
+                        .fields_except(
+                            i,
+                            |field| Some(field.generic_ident.to_token_stream()),
+                            |_| None,
+                        )
+                        .flatten(),
+                );
+                let set_generics = self.ty_generics(self.fields.fields_except(
+                    i,
+                    |field| field.generic_ident.to_token_stream(),
+                    |_| ty.to_token_stream(),
+                ));
+                quote! {
+                    #[automatically_derived]
+                    impl <#(#set_impl_generics),*> #builder_ident <#(#set_generics),*> #where_clause {
+                        #(#more_setters)*
+                    }
+                }
+            } else {
+                quote!()
+            };
+            Some(quote! {
+                #[automatically_derived]
+                impl <#(#impl_generics),*> #builder_ident <#(#in_ty_generics),*> #where_clause {
+                    #(#setters)*
+                }
+
+                #more_setters
+            })
+        })
+    }
+
+    fn relation_trait(&self, relation: &Relation) -> Ident {
+        let fields = self.fields.fields().collect::<Vec<_>>();
+        let kind = match relation.kind {
+            RelationKind::Requires => "Requires",
+            RelationKind::ConflictsWith => "ConflictsWith",
+        };
+        quote::format_ident!(
+            "__{}{}{}{}",
+            self.builder_ident,
+            fields[relation.field].name.to_case(Case::UpperCamel),
+            kind,
+            fields[relation.other].name.to_case(Case::UpperCamel),
+        )
+    }
+
+    fn relation_traits(&'a self) -> impl Iterator<Item = TokenStream> + 'a {
+        let fields = self.fields.fields().collect::<Vec<_>>();
+        let vis = self.vis;
+        self.relations.iter().map(move |relation| {
+            let trait_ident = self.relation_trait(relation);
+            let field = &fields[relation.field].name;
+            let other = &fields[relation.other].name;
+            match relation.kind {
+                RelationKind::Requires => {
+                    let message = format!("`{}` requires `{}` to be set", field, other);
+                    let label = format!("`{}` is set, but `{}` is not", field, other);
+                    quote! {
+                        #[doc(hidden)]
+                        #[diagnostic::on_unimplemented(message = #message, label = #label)]
+                        #vis trait #trait_ident<__Field, __Other> {}
+                        impl<__Field, __Other, __State> #trait_ident<__Field, __Other> for (::builder::NoData<__Field>, __State) {}
+                        impl<__Field, __Other> #trait_ident<__Field, __Other> for (__Field, __Other) {}
+                    }
+                }
+                RelationKind::ConflictsWith => {
+                    let message = format!("`{}` and `{}` cannot both be set", field, other);
+                    quote! {
+                        #[doc(hidden)]
+                        #[diagnostic::on_unimplemented(message = #message)]
+                        #vis trait #trait_ident {}
+                        impl<__T> #trait_ident for ::builder::NoData<__T> {}
+                    }
+                }
+            }
+        })
+    }
+
+    /// Bounds keeping field `i` from being set while a field it conflicts with is set.
+    fn conflicts(&self, i: usize) -> Vec<TokenStream> {
+        let fields = self.fields.fields().collect::<Vec<_>>();
+        self.relations
+            .iter()
+            .filter(|relation| relation.kind == RelationKind::ConflictsWith)
+            .filter_map(|relation| {
+                let other = if relation.field == i {
+                    relation.other
+                } else if relation.other == i {
+                    relation.field
+                } else {
+                    return None;
+                };
+                // a field a `steps` builder has not reached is unset
+                if self.locked(i, other) {
+                    return None;
+                }
+                let generic_ident = &fields[other].generic_ident;
+                let trait_ident = self.relation_trait(relation);
+                Some(quote!(#generic_ident: #trait_ident))
+            })
+            .collect()
+    }
+
+    /// Bounds requiring the `requires` relations to hold once the builder is complete.
+    fn requirements(&self) -> Vec<TokenStream> {
+        let fields = self.fields.fields().collect::<Vec<_>>();
+        let state = |field: &Field| {
             if field.default {
-                Some(field.generic_ident.to_token_stream())
+                field.generic_ident.to_token_stream()
             } else {
-                None
+                field.ty.to_token_stream()
             }
-        }));
+        };
+        self.relations
+            .iter()
+            .filter(|relation| relation.kind == RelationKind::Requires)
+            .map(|relation| {
+                let field = fields[relation.field];
+                let other = fields[relation.other];
+                let (field_state, other_state) = (state(field), state(other));
+                let (field_ty, other_ty) = (&field.ty, &other.ty);
+                let trait_ident = self.relation_trait(relation);
+                quote!((#field_state, #other_state): #trait_ident<#field_ty, #other_ty>)
+            })
+            .collect()
+    }
+
+    fn getters(&'a self) -> impl Iterator<Item = TokenStream> + 'a {
+        let fields = self.fields.fields().enumerate();
+        fields
+            .filter(|(_, field)| !field.flatten && field.group.is_none())
+            .map(|(i, field)| {
+                let impl_generics = self.impl_generics(
+                    self.fields
+                        .fields_except(
+                            i,
+                            |field| Some(field.generic_ident.to_token_stream()),
+                            |_| None,
+                        )
+                        .flatten(),
+                );
+                let builder_ident = &self.builder_ident;
+                let ty = &field.ty;
+                let ty_generics = self.ty_generics(self.fields.fields_except(
+                    i,
+                    |field| field.generic_ident.to_token_stream(),
+                    |_| ty.to_token_stream(),
+                ));
+                let where_clause = self.where_clause();
+                let getter = &field.getter;
+                let field_ident = &field.field_ident;
+                let vis = self.vis;
+                quote! {
+                    #[automatically_derived]
+                    impl <#(#impl_generics),*> #builder_ident <#(#ty_generics),*> #where_clause {
+                        #vis fn #getter(&self) -> &#ty {
+                            &self.#field_ident
+                        }
+                    }
+                }
+            })
+    }
+
+    fn field_traits(&'a self) -> impl Iterator<Item = TokenStream> + 'a {
+        self.fields.fields().enumerate().filter_map(|(i, field)| {
+            let FieldTrait { module, set, has } = field.field_trait.as_ref()?;
+            let vis = self.vis;
+            let setter = &field.setter;
+            let getter = &field.getter;
+            // with a path, the traits are reused from wherever another struct defined them
+            let definitions = if module.is_none() {
+                quote! {
+                    #vis trait #set {
+                        type Value;
+                        type Output;
+
+                        fn #setter(self, value: Self::Value) -> Self::Output;
+                    }
+
+                    #vis trait #has {
+                        type Value;
+
+                        fn #getter(&self) -> &Self::Value;
+                    }
+                }
+            } else {
+                quote!()
+            };
+            let module = module
+                .as_ref()
+                .map_or_else(|| quote!(self), ToTokens::to_token_stream);
+
+            let builder_ident = &self.builder_ident;
+            let ty = &field.ty;
+            let set_impl_generics = self.impl_generics(self.free_generics(i, false));
+            let unset_generics = self.ty_generics(self.states_with(i, quote!(::builder::NoData<#ty>)));
+            let out_generics = self.ty_generics(self.states_after(i, ty.to_token_stream()));
+            let impl_generics = self.impl_generics(
+                self.fields
+                    .fields_except(
+                        i,
+                        |field| Some(field.generic_ident.to_token_stream()),
+                        |_| None,
+                    )
+                    .flatten(),
+            );
+            let set_generics = self.ty_generics(self.fields.fields_except(
+                i,
+                |field| field.generic_ident.to_token_stream(),
+                |_| ty.to_token_stream(),
+            ));
+            let where_clause = self.where_clause();
+            let conflicts = self.conflicts(i);
+            let with_value = self.with_field(i, quote!(self), quote!(value));
+
+            Some(quote! {
+                #definitions
+
+                #[automatically_derived]
+                impl <#(#set_impl_generics),*> #module::#set for #builder_ident <#(#unset_generics),*> #where_clause #(, #conflicts)* {
+                    type Value = #ty;
+                    type Output = #builder_ident <#(#out_generics),*>;
+
+                    fn #setter(self, value: #ty) -> Self::Output {
+                        #with_value
+                    }
+                }
+
+                #[automatically_derived]
+                impl <#(#impl_generics),*> #module::#has for #builder_ident <#(#set_generics),*> #where_clause {
+                    type Value = #ty;
+
+                    fn #getter(&self) -> &#ty {
+                        #builder_ident::#getter(self)
+                    }
+                }
+            })
+        })
+    }
+
+    fn with_field(&self, i: usize, parent: TokenStream, value: TokenStream) -> TokenStream {
+        let builder_ident = &self.builder_ident;
+        let fields = self.fields.fields().enumerate().map(|(j, field)| {
+            let field_ident = &field.field_ident;
+            if i == j {
+                // this is the field we are writing the impl for; fill in `value`
+                quote!(#field_ident: #value)
+            } else if self.unlocks(i, j) {
+                quote!(#field_ident: ::builder::NoData::new())
+            } else {
+                // otherwise propagate the parent's value
+                quote!(#field_ident: #parent.#field_ident)
+            }
+        });
+        quote! {
+            #builder_ident {
+                _built: ::core::marker::PhantomData,
+                callback: #parent.callback,
+                #(#fields,)*
+            }
+        }
+    }
+
+    fn accumulators(&'a self) -> impl Iterator<Item = TokenStream> + 'a {
+        self.fields
+            .fields()
+            .enumerate()
+            .flat_map(move |(i, field)| {
+                let pusher = field
+                    .push
+                    .as_ref()
+                    .map(|pusher| self.accumulator(i, field, pusher, false));
+                let inserter = field
+                    .insert
+                    .as_ref()
+                    .map(|inserter| self.accumulator(i, field, inserter, true));
+                pusher.into_iter().chain(inserter)
+            })
+    }
+
+    fn accumulator(&'a self, i: usize, field: &Field, method: &Ident, keyed: bool) -> TokenStream {
+        let generic_ident = &field.generic_ident;
+        let impl_generics = self.impl_generics(self.free_generics(i, true));
+        let impl_generics = quote!(<#(#impl_generics),*>);
         let builder_ident = &self.builder_ident;
-        let builder_ty_generics = self.ty_generics(self.fields.completed_generics());
+        let in_ty_generics = self.ty_generics(self.states_with(i, generic_ident.to_token_stream()));
+        let in_ty = quote!(#builder_ident <#(#in_ty_generics),*>);
+        let ty = &field.ty;
+        let item = quote!(<#ty as ::core::iter::IntoIterator>::Item);
+        let accumulated = quote!(<#generic_ident as ::builder::Accumulate<#ty, #item>>::Output);
+        let out_ty_generics = self.ty_generics(self.states_with(i, accumulated));
+        let out_ty = quote!(#builder_ident <#(#out_ty_generics),*>);
+        let conflicts = self.conflicts(i);
+        let base_where_clause = self.where_clause();
+        let base_where_clause = quote!(#base_where_clause #(, #conflicts)*);
+        let where_clause =
+            quote!(#base_where_clause, #generic_ident: ::builder::Accumulate<#ty, #item>);
+
+        // a fallible nested builder leaves the collection's state a `Result`, holding the first
+        // error until `try_build`
+        let try_impl_generics =
+            self.impl_generics(self.free_generics(i, true).chain(once(quote!(__Error))));
+        let try_impl_generics = quote!(<#(#try_impl_generics),*>);
+        let try_out_ty_generics =
+            self.ty_generics(self.states_with(i, quote!(::core::result::Result<#ty, __Error>)));
+        let try_out_ty = quote!(#builder_ident <#(#try_out_ty_generics),*>);
+        let try_where_clause = quote! {
+            #base_where_clause, #generic_ident: ::builder::TryAccumulate<#ty, #item, __Error>
+        };
+
+        // map fields take the key up front and build only the value
+        let (kind, built, key_field, key_param, key_arg, entry) = if keyed {
+            let key = quote!(<#item as ::builder::MapEntry>::Key);
+            (
+                "Insert",
+                quote!(<#item as ::builder::MapEntry>::Value),
+                quote!(key: #key,),
+                quote!(key: #key),
+                quote!(key,),
+                quote!((self.key, val)),
+            )
+        } else {
+            (
+                "Push",
+                item.clone(),
+                quote!(),
+                quote!(),
+                quote!(),
+                quote!(val),
+            )
+        };
+
+        let field_ident = &field.field_ident;
+        let with_item = self.with_field(
+            i,
+            quote!(parent),
+            quote!(::builder::Accumulate::accumulate(parent.#field_ident, #entry)),
+        );
+        let with_result = self.with_field(
+            i,
+            quote!(parent),
+            quote!(::builder::TryAccumulate::try_accumulate(parent.#field_ident, val.map(|val| #entry))),
+        );
+
+        let callback_ident = quote::format_ident!("__{}{}{}", builder_ident, kind, generic_ident);
+        let callback_def = quote!(#callback_ident #impl_generics);
+        let callback_ty_generics = self.ty_generics(self.free_generics(i, true));
+        let callback_use = quote!(#callback_ident <#(#callback_ty_generics),*>);
+        let vis = self.vis;
+        let struct_where_clause = self.struct_where_clause();
+
+        quote! {
+            #[doc(hidden)]
+            #[automatically_derived]
+            #[allow(non_camel_case_types)]
+            #vis struct #callback_def #struct_where_clause {
+                parent: #in_ty,
+                #key_field
+            }
+            #[automatically_derived]
+            impl #impl_generics ::builder::Callback<#built> for #callback_use #where_clause {
+                type Output = #out_ty;
+                fn callback(self, val: #built) -> Self::Output {
+                    let parent = self.parent;
+                    #with_item
+                }
+            }
+            #[automatically_derived]
+            impl #try_impl_generics ::builder::TryCallback<#built, __Error> for #callback_use #try_where_clause {
+                type Output = #try_out_ty;
+                fn try_callback(self, val: ::core::result::Result<#built, __Error>) -> Self::Output {
+                    let parent = self.parent;
+                    #with_result
+                }
+            }
+
+            #[automatically_derived]
+            impl #impl_generics #in_ty #where_clause, #built: ::builder::BuilderWithCallback<#callback_use> {
+                #vis fn #method(self, #key_param) -> <#built as ::builder::BuilderWithCallback<#callback_use>>::CallbackBuilder {
+                    <#built as ::builder::BuilderWithCallback<#callback_use>>::builder_with_callback(#callback_ident { parent: self, #key_arg })
+                }
+            }
+        }
+    }
+
+    /// `let` statements resolving each field's state to its final value, with plain fields first
+    /// and then computed defaults in dependency order. `error` is the error type for `try_build`.
+    fn resolve_fields(&self, error: Option<&TokenStream>) -> TokenStream {
+        let fields = self.fields.all().collect::<Vec<_>>();
+        // groups come first, as their members are read from them
+        let groups = self.fields.fields().filter(|field| field.group.is_some());
+        let plain = fields
+            .iter()
+            .copied()
+            .filter(|field| field.default_expr.is_none());
+        let computed = self.default_order.iter().map(|&i| fields[i]);
+        groups
+            .chain(plain)
+            .chain(computed)
+            .filter_map(|field| {
+                let field_ident = &field.field_ident;
+                let generic_ident = &field.generic_ident;
+                let ty = &field.ty;
+                if let Some((group, k)) = self.fields.group_of(field) {
+                    let group_ident = &group.field_ident;
+                    let k = Literal::usize_unsuffixed(k);
+                    return Some(quote!(let #field_ident: #ty = #group_ident.#k;));
+                }
+                let value = match (&field.default_expr, error) {
+                    (Some(default_expr), _) if field.skip => {
+                        let body = default_expr.value();
+                        quote!(#body)
+                    }
+                    (None, _) if field.skip => quote!(<#ty as ::core::default::Default>::default()),
+                    (None, _) if field.flatten => {
+                        quote!(<#generic_ident as ::builder::Build>::build(#field_ident))
+                    }
+                    (Some(default_expr), None) => {
+                        let body = default_expr.value();
+                        quote!(<#generic_ident as ::builder::OrElse<#ty>>::or_else(#field_ident, || #body))
+                    }
+                    (Some(default_expr), Some(error)) => {
+                        let body = default_expr.value();
+                        quote!(<#generic_ident as ::builder::TryOrElse<#ty, #error>>::try_or_else(#field_ident, || #body)?)
+                    }
+                    (None, None) if field.default => {
+                        quote!(<#generic_ident as ::builder::OrDefault<#ty>>::or_default(#field_ident))
+                    }
+                    (None, None) => return None,
+                    (None, Some(error)) if field.default => {
+                        quote!(<#generic_ident as ::builder::TryOrDefault<#ty, #error>>::try_or_default(#field_ident)?)
+                    }
+                    (None, Some(error)) => {
+                        quote!(<#generic_ident as ::builder::TryField<#ty, #error>>::try_field(#field_ident)?)
+                    }
+                };
+                Some(quote!(let #field_ident: #ty = #value;))
+            })
+            .collect()
+    }
+
+    fn build(&self) -> TokenStream {
+        let callback = &self.callback;
+        let impl_generics = self
+            .impl_generics(self.fields.fields().filter_map(|field| {
+                if field.default || field.flatten {
+                    Some(field.generic_ident.to_token_stream())
+                } else {
+                    None
+                }
+            }))
+            .collect::<Vec<_>>();
+        let builder_ident = &self.builder_ident;
+        let builder_ty_generics = self
+            .ty_generics(self.fields.completed_generics())
+            .collect::<Vec<_>>();
+        let builder_ty = quote!(#builder_ident <#(#builder_ty_generics),*>);
         let where_clause = self.where_clause();
-        let default_wheres = self.fields.fields().filter_map(
+        let default_wheres = self
+            .fields
+            .fields()
+            .filter_map(
+                |Field {
+                     default,
+                     default_expr,
+                     flatten,
+                     generic_ident,
+                     ty,
+                     ..
+                 }| {
+                    if *flatten {
+                        Some(quote!(#generic_ident: ::builder::Build<Output = #ty>))
+                    } else if default_expr.is_some() {
+                        Some(quote!(#generic_ident: ::builder::OrElse<#ty>))
+                    } else if *default {
+                        Some(quote!(#generic_ident: ::builder::OrDefault<#ty>))
+                    } else {
+                        None
+                    }
+                },
+            )
+            .collect::<Vec<_>>();
+        let built_ty = self.built_ty();
+
+        let field_idents = self.fields.fields().map(|field| &field.field_ident);
+        let resolved = self.resolve_fields(None);
+        let built = self.construct(&self.built_path(), |field| {
+            field.field_ident.to_token_stream()
+        });
+        let requirements = self.requirements();
+        let build_fn = &self.build_fn;
+        let vis = self.vis;
+        // a foreign type cannot implement `From`
+        let from = if self.remote.is_some() {
+            quote!()
+        } else {
+            quote! {
+                #[automatically_derived]
+                impl <#(#impl_generics),*> ::core::convert::From<#builder_ty> for #built_ty
+                #where_clause, #callback: ::builder::Callback<#built_ty, Output = #built_ty> #(, #default_wheres)* #(, #requirements)*
+                {
+                    fn from(builder: #builder_ty) -> Self {
+                        <#builder_ty as ::builder::Build>::build(builder)
+                    }
+                }
+            }
+        };
+        quote! {
+            #[automatically_derived]
+            impl <#(#impl_generics),*> ::builder::Build for #builder_ty
+            #where_clause #(, #default_wheres)* #(, #requirements)*
+            {
+                type Output = #callback::Output;
+
+                fn build(self) -> Self::Output {
+                    let #builder_ident { callback, #(#field_idents,)* .. } = self;
+                    #resolved
+                    callback.callback(#built)
+                }
+            }
+
+            #[automatically_derived]
+            impl <#(#impl_generics),*> #builder_ty
+            #where_clause, #(#default_wheres),*
+            {
+                #vis fn #build_fn(self) -> #callback::Output
+                where
+                    #(#requirements,)*
+                {
+                    <Self as ::builder::Build>::build(self)
+                }
+            }
+
+            #from
+        }
+    }
+
+    fn try_build(&self) -> TokenStream {
+        let callback = &self.callback;
+        let error = quote!(__Error);
+        let impl_generics = self.impl_generics(self.fields.generics());
+        let builder_ident = &self.builder_ident;
+        let builder_ty_generics = self.ty_generics(self.fields.generics());
+        let where_clause = self.where_clause();
+        let built_ty = self.built_ty();
+        let field_wheres = self.fields.fields().map(
             |Field {
                  default,
+                 default_expr,
+                 flatten,
                  generic_ident,
                  ty,
                  ..
              }| {
-                if *default {
-                    Some(quote!(#generic_ident: ::builder::OrDefault<#ty>))
+                if *flatten {
+                    quote!(#generic_ident: ::builder::Build<Output = #ty>)
+                } else if default_expr.is_some() {
+                    quote!(#generic_ident: ::builder::TryOrElse<#ty, #error>)
+                } else if *default {
+                    quote!(#generic_ident: ::builder::TryOrDefault<#ty, #error>)
                 } else {
-                    None
+                    quote!(#generic_ident: ::builder::TryField<#ty, #error>)
                 }
             },
         );
+        let (validate_where, validate) = if self.validate {
+            (
+                quote!(#built_ty: ::builder::Validate, <#built_ty as ::builder::Validate>::Error: ::core::convert::Into<#error>,),
+                quote!(::builder::Validate::validate(&built).map_err(::core::convert::Into::into)?;),
+            )
+        } else {
+            (quote!(), quote!())
+        };
 
-        let ident = self.ident;
+        // a fallible nested builder may store its result in a field, so the `requires` relations
+        // are checked when building rather than by the typestate
+        let fields = self.fields.fields().collect::<Vec<_>>();
+        let (requires_wheres, requires_checks): (Vec<_>, Vec<_>) = self
+            .relations
+            .iter()
+            .filter(|relation| relation.kind == RelationKind::Requires)
+            .map(|relation| {
+                let field = fields[relation.field];
+                let other = fields[relation.other];
+                let (field_generic, other_generic) = (&field.generic_ident, &other.generic_ident);
+                let (field_ty, other_ty) = (&field.ty, &other.ty);
+                let (field_ident, other_ident) = (&field.field_ident, &other.field_ident);
+                let (field_name, other_name) = (&field.name, &other.name);
+                (
+                    quote! {
+                        #field_generic: ::builder::IsSet<#field_ty>,
+                        #other_generic: ::builder::IsSet<#other_ty>,
+                        ::builder::RequiresError: ::core::convert::Into<#error>,
+                    },
+                    quote! {
+                        if <#field_generic as ::builder::IsSet<#field_ty>>::is_set(&#field_ident)
+                            && !<#other_generic as ::builder::IsSet<#other_ty>>::is_set(&#other_ident)
+                        {
+                            return ::core::result::Result::Err(::core::convert::Into::into(
+                                ::builder::RequiresError { field: #field_name, requires: #other_name },
+                            ));
+                        }
+                    },
+                )
+            })
+            .unzip();
 
-        let fields = match &self.fields {
-            Fields::Named(fields) => {
-                let fields = fields.iter().map(|field| {
-                    let ident = field.ident;
-                    let field_ident = &field.field.field_ident;
-                    if field.field.default {
-                        let generic_ident = &field.field.generic_ident;
-                        let ty = field.field.ty;
-                        quote!(#ident: <#generic_ident as ::builder::OrDefault<#ty>>::or_default(self.#field_ident))
-                    } else {
-                        quote!(#ident: self.#field_ident)
-                    }
-                });
-                quote!({ #(#fields),* })
+        let field_idents = self.fields.fields().map(|field| &field.field_ident);
+        let resolved = self.resolve_fields(Some(&error));
+        let try_build_fn = quote::format_ident!("try_{}", self.build_fn);
+        let vis = self.vis;
+        let built = self.construct(&self.built_path(), |field| {
+            field.field_ident.to_token_stream()
+        });
+        quote! {
+            #[automatically_derived]
+            impl <#(#impl_generics),*> #builder_ident <#(#builder_ty_generics),*> #where_clause {
+                #vis fn #try_build_fn<#error>(self) -> <#callback as ::builder::TryCallback<#built_ty, #error>>::Output
+                where
+                    #(#field_wheres,)*
+                    #validate_where
+                    #(#requires_wheres)*
+                    #callback: ::builder::TryCallback<#built_ty, #error>,
+                {
+                    let #builder_ident { callback, #(#field_idents,)* .. } = self;
+                    let built = (move || -> ::core::result::Result<#built_ty, #error> {
+                        #(#requires_checks)*
+                        #resolved
+                        let built = #built;
+                        #validate
+                        ::core::result::Result::Ok(built)
+                    })();
+                    ::builder::TryCallback::try_callback(callback, built)
+                }
             }
-            Fields::Unnamed(fields) => {
-                let fields = fields.iter().map(|field| {
-                    let field_ident = &field.field.field_ident;
-                    if field.field.default {
-                        let generic_ident = &field.field.generic_ident;
-                        let ty = field.field.ty;
-                        quote!(<#generic_ident as ::builder::OrDefault<#ty>>::or_default(self.#field_ident))
-                    } else {
-                        quote!(self.#field_ident)
+        }
+    }
+
+    fn flattened(&'a self) -> impl Iterator<Item = TokenStream> + 'a {
+        let fields = self.fields.fields().enumerate();
+        fields.filter(|(_, field)| field.flatten).map(|(i, field)| {
+            // the child's state macro lists its fields and passes them on to `__flatten!`
+            let child_macro = match field.flatten_macro() {
+                Ok(child_macro) => child_macro,
+                Err(error) => return error.to_compile_error(),
+            };
+
+            let impl_generics = self.impl_generics(self.fields.generics()).collect::<Vec<_>>();
+            let builder_ident = &self.builder_ident;
+            let ty_generics = self.ty_generics(self.fields.generics());
+            let self_ty = quote!(#builder_ident <#(#ty_generics),*>);
+            let where_clause = self.where_clause();
+            let generic_ident = &field.generic_ident;
+            let field_ident = &field.field_ident;
+            let index = Literal::usize_unsuffixed(i);
+            let new = quote!(__New);
+            let out_ty_generics = self.ty_generics(self.fields.fields_except(
+                i,
+                |field| field.generic_ident.to_token_stream(),
+                |_| new.clone(),
+            ));
+            let with_mapped = self.with_field(i, quote!(self), quote!(f(self.#field_ident)));
+            let prefix = &field.prefix;
+            let vis = self.vis;
+            quote! {
+                #[automatically_derived]
+                impl <#(#impl_generics),*> ::builder::MapFlattened<#index> for #self_ty #where_clause {
+                    type Field = #generic_ident;
+                    type Output<#new> = #builder_ident <#(#out_ty_generics),*>;
+
+                    fn map_flattened<#new>(self, f: impl FnOnce(#generic_ident) -> #new) -> Self::Output<#new> {
+                        #with_mapped
                     }
-                });
-                quote!((#(#fields),*))
+                }
+
+                #child_macro! {
+                    @flatten
+                    { #generic_ident }
+                    { #(#impl_generics),* }
+                    { #self_ty }
+                    { #where_clause }
+                    { #index }
+                    { #prefix }
+                    { #vis }
+                }
             }
-            Fields::Unit => quote!(),
+        })
+    }
+
+    fn state_macro(&self) -> TokenStream {
+        let builder_ident = &self.builder_ident;
+        let macro_ident = &self.macro_ident;
+        let built_ty = self.built_ty();
+
+        // the struct's generic arguments, in declaration order, as macro fragments
+        let args = self
+            .generics
+            .params()
+            .iter()
+            .enumerate()
+            .map(|(k, param)| (quote::format_ident!("__g{}", k), param))
+            .collect::<Vec<_>>();
+        let arg_pattern = args.iter().map(|(var, param)| match param {
+            GenericParam::Lifetime(_) => quote!($#var:lifetime),
+            GenericParam::Type(_) => quote!($#var:ty),
+            GenericParam::Const(_) => quote!($#var:tt),
+        });
+        let arg_pattern = if args.is_empty() {
+            quote!()
+        } else {
+            quote!(<#(#arg_pattern),*>)
+        };
+        let args_of = |kind: fn(&GenericParam) -> bool| {
+            args.iter()
+                .filter(move |(_, param)| kind(param))
+                .map(|(var, _)| quote!($#var))
         };
+        let placeholders = once(quote!(())).chain(self.fields.fields().map(|_| quote!(())));
+        let seed_args = args_of(|param| matches!(param, GenericParam::Lifetime(_)))
+            .chain(args_of(|param| matches!(param, GenericParam::Type(_))))
+            .chain(placeholders)
+            .chain(args_of(|param| matches!(param, GenericParam::Const(_))));
+        let seed = quote!(#builder_ident <#(#seed_args),*>);
+
+        // the fields `FooBuilder![...]` can name, with a flattened field taking its builder's state
+        let field_specs = self
+            .fields
+            .fields()
+            .zip(self.fields.names())
+            .map(|(field, name)| {
+                if field.flatten {
+                    quote!((#name flatten))
+                } else {
+                    quote!((#name))
+                }
+            });
+        let flatten_fields = self
+            .fields
+            .fields()
+            .zip(self.fields.names())
+            .enumerate()
+            .filter(|(_, (field, _))| !field.flatten && field.group.is_none())
+            .map(|(k, (_, name))| {
+                let index = Literal::usize_unsuffixed(k);
+                quote!(([#index] #name))
+            });
+        // the fields of flattened fields are listed too, once their macros have added them
+        let pending = self
+            .fields
+            .fields()
+            .enumerate()
+            .filter(|(_, field)| field.flatten)
+            .filter_map(|(k, field)| {
+                let index = Literal::usize_unsuffixed(k);
+                let prefix = &field.prefix;
+                let child_macro = field.flatten_macro().ok()?;
+                Some(quote!((#index { #prefix } #child_macro)))
+            });
+
+        // a public struct's macro is exported from the crate root, and re-exported next to the
+        // builder, so it is numbered to be unique in the crate
+        let (export, vis) = match self.vis {
+            Visibility::Public(_) => (quote!(#[macro_export]), self.vis.to_token_stream()),
+            Visibility::Crate(_) => (quote!(), quote!(pub(crate))),
+            vis => (quote!(), vis.to_token_stream()),
+        };
+        let struct_generics = self.generics.impl_generics(empty());
+        let where_predicates = self.generics.where_predicates();
+        let new_callback = quote!(__NewCallback);
+        let state_generics = self
+            .fields
+            .fields()
+            .map(|field| quote::format_ident!("__State{}", field.generic_ident))
+            .collect::<Vec<_>>();
+        // a field a `steps` builder locks stays locked until the slot before it is set
+        let state_traits = self
+            .fields
+            .fields()
+            .enumerate()
+            .map(|(j, field)| {
+                let ty = &field.ty;
+                let steps = self.steps.as_deref().unwrap_or_default();
+                let before = match self.step(j) {
+                    Some(q) => q.checked_sub(1).map(|p| steps[p]),
+                    None => steps.last().copied(),
+                };
+                if field.flatten {
+                    quote!(::builder::FlattenState<#ty>)
+                } else if let Some(before) = before {
+                    let before = &state_generics[before];
+                    quote!(::builder::StepState<#ty, #before>)
+                } else {
+                    quote!(::builder::FieldState<#ty>)
+                }
+            })
+            .collect::<Vec<_>>();
+        let state_bounds = state_generics
+            .iter()
+            .zip(&state_traits)
+            .map(|(state, state_trait)| quote!(#state: #state_trait));
+        let impl_seed_args = self
+            .generics
+            .ty_generics(once(quote!(())).chain(self.fields.fields().map(|_| quote!(()))));
+        let impl_seed = quote!(#builder_ident <#(#impl_seed_args),*>);
+        let builder_args = self.generics.ty_generics(
+            once(new_callback.to_token_stream()).chain(
+                state_generics
+                    .iter()
+                    .zip(&state_traits)
+                    .map(|(state, state_trait)| quote!(<#state as #state_trait>::State)),
+            ),
+        );
         quote! {
             #[automatically_derived]
-            impl <#(#impl_generics),*> #builder_ident <#(#builder_ty_generics),*>
-            #where_clause, #(#default_wheres),*
+            impl <#(#struct_generics,)* #new_callback, #(#state_bounds),*>
+                ::builder::BuilderState<#new_callback, (#(#state_generics,)*)> for #impl_seed
+            where
+                #(#where_predicates,)*
             {
-                fn build(self) -> #callback::Output {
-                    self.callback.callback(#ident #fields)
-                }
+                type Built = #built_ty;
+                type Builder = #builder_ident <#(#builder_args),*>;
+            }
+
+            ::builder::__state_macro! {
+                [#(#flatten_fields)*]
+                [#(#pending)*]
+                { #export }
+                { #macro_ident }
+                { #vis }
+                { #builder_ident }
+                { #arg_pattern }
+                { #seed }
+                { #(#field_specs)* }
             }
         }
     }
+
+    fn construct(
+        &self,
+        ident: &TokenStream,
+        mut value: impl FnMut(&Field) -> TokenStream,
+    ) -> TokenStream {
+        match &self.fields.shape {
+            Shape::Named(fields) => {
+                let fields = fields.iter().map(|field| {
+                    let ident = field.ident;
+                    let value = value(&field.field);
+                    quote!(#ident: #value)
+                });
+                quote!(#ident { #(#fields),* })
+            }
+            Shape::Unnamed(fields) => {
+                let fields = fields.iter().map(|field| value(&field.field));
+                quote!(#ident (#(#fields),*))
+            }
+            Shape::Unit => quote!(#ident),
+        }
+    }
 }
 
 impl<'a> ToTokens for StructAttrs<'a> {
@@ -282,16 +1479,32 @@ impl<'a> ToTokens for StructAttrs<'a> {
         let builder_ident = &self.builder_ident;
         let builder_generics = self.builder_generics();
 
-        let generic_markers = self.generics.field_definitions();
+        let built_ty = self.built_ty();
         let callback = &self.callback;
         let fields = self.fields.field_definitions();
         let default_constructor = self.default_constructor();
         let setters = self.setters();
-        let build = self.build();
+        let group_setters = self.group_setters();
+        let relation_traits = self.relation_traits();
+        let accumulators = self.accumulators();
+        let getters = self.getters();
+        let field_traits = self.field_traits();
+        // validated structs can only be built through `try_build`
+        let build = if self.validate {
+            quote!()
+        } else {
+            self.build()
+        };
+        let try_build = self.try_build();
+        let flattened = self.flattened();
+        let state_macro = self.state_macro();
+        let where_clause = self.struct_where_clause();
         let stream = quote! {
             #[automatically_derived]
-            #vis struct #builder_ident <#(#builder_generics),*> {
-                #(#generic_markers,)*
+            #vis struct #builder_ident <#(#builder_generics),*> #where_clause {
+                // gives the builder the struct's variance and implied bounds, such as `T: 'a`
+                // for a `&'a T` field, which its impls rely on
+                _built: ::core::marker::PhantomData<fn() -> #built_ty>,
                 callback: #callback,
                 #(#fields,)*
             }
@@ -300,26 +1513,90 @@ impl<'a> ToTokens for StructAttrs<'a> {
 
             #(#setters)*
 
+            #(#group_setters)*
+
+            #(#relation_traits)*
+
+            #(#accumulators)*
+
+            #(#getters)*
+
+            #(#field_traits)*
+
             #build
+
+            #try_build
+
+            #(#flattened)*
+
+            #state_macro
         };
         tokens.extend(stream)
     }
 }
 
+/// The number of state macros defined so far, which numbers each one, as an exported macro
+/// needs a name unique in its crate.
+static STATE_MACROS: AtomicUsize = AtomicUsize::new(0);
+
 impl<'a> TryFrom<&'a DeriveInput> for StructAttrs<'a> {
     type Error = Error;
 
     fn try_from(input: &'a DeriveInput) -> Result<Self, Self::Error> {
-        let builder_ident = quote::format_ident!("{}Builder", &input.ident);
+        let mut validate = false;
+        let mut steps = None;
+        let mut remote = None;
+        for attr in attrs::parse(&input.attrs)? {
+            if attr.is("validate") {
+                validate = true;
+            } else if attr.is("steps") {
+                steps = Some(attr.name.span());
+            } else if attr.is("remote") {
+                remote = Some(attr.path_value()?.ok_or_else(|| {
+                    syn::Error::new(attr.name.span(), "expected `remote = \"path\"`")
+                })?);
+            } else {
+                return Err(attr.unexpected().into());
+            }
+        }
         match &input.data {
-            syn::Data::Struct(data) => Ok(StructAttrs {
-                vis: &input.vis,
-                ident: &input.ident,
-                builder_ident,
-                callback: syn::parse_quote!(__Callback),
-                generics: (&input.generics).into(),
-                fields: (&data.fields).try_into()?,
-            }),
+            syn::Data::Struct(data) => {
+                let fields = Fields::try_from(&data.fields)?;
+                if input
+                    .attrs
+                    .iter()
+                    .any(|attr| attr.path.is_ident("non_exhaustive"))
+                {
+                    fields.check_non_exhaustive()?;
+                }
+                // a mirror's builder is named after the type it builds
+                let builder_ident = match &remote {
+                    Some(remote) => {
+                        quote::format_ident!("{}Builder", remote.segments.last().unwrap().ident)
+                    }
+                    None => quote::format_ident!("{}Builder", &input.ident),
+                };
+                let macro_ident = quote::format_ident!(
+                    "__{}_state_{}",
+                    builder_ident,
+                    STATE_MACROS.fetch_add(1, Ordering::Relaxed)
+                );
+                Ok(StructAttrs {
+                    vis: &input.vis,
+                    ident: &input.ident,
+                    builder_ident,
+                    callback: syn::parse_quote!(__Callback),
+                    generics: (&input.generics).into(),
+                    default_order: fields.default_order()?,
+                    relations: fields.relations()?,
+                    steps: steps.map(|span| fields.steps(span)).transpose()?,
+                    fields,
+                    validate,
+                    remote,
+                    build_fn: syn::parse_quote!(build),
+                    macro_ident,
+                })
+            }
             syn::Data::Enum(_) => Err(Error::InvalidShape("struct", "enum")),
             syn::Data::Union(_) => Err(Error::InvalidShape("struct", "union")),
         }
@@ -0,0 +1,226 @@
+use std::iter::once;
+
+use proc_macro2::{TokenStream, TokenTree};
+use quote::{format_ident, quote, ToTokens};
+use syn::parse::{Parse, ParseStream};
+use syn::{braced, bracketed, parenthesized, Ident, LitInt, Path, Token, Type};
+
+/// The input of `__state!`: the builder's description, as emitted by its state macro, followed
+/// by the fields listed in `FooBuilder![...]` and its optional callback.
+pub struct StateInput {
+    /// The builder with every field in the `()` state, applied to the macro's generic arguments.
+    seed: TokenStream,
+    /// Each field's state: `Unset`, `Set`, or `Flattened<B>`.
+    states: Vec<TokenStream>,
+    callback: Option<Type>,
+}
+
+impl Parse for StateInput {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let seed;
+        bracketed!(seed in input);
+        let seed = seed.parse()?;
+        let list;
+        bracketed!(list in input);
+        let mut fields = Vec::new();
+        while !list.is_empty() {
+            let field;
+            parenthesized!(field in list);
+            let name = field.parse::<TokenTree>()?.to_string();
+            let flatten = field.parse::<Option<Ident>>()?.is_some();
+            fields.push((name, flatten));
+        }
+
+        let mut states = vec![quote!(::builder::Unset); fields.len()];
+        let mut callback = None;
+        while !input.is_empty() {
+            if input.parse::<Option<Token![,]>>()?.is_some() {
+                continue;
+            }
+            if input.parse::<Option<Token![;]>>()?.is_some() {
+                callback = Some(input.parse()?);
+                break;
+            }
+            let name = input.parse::<TokenTree>()?;
+            let index = fields
+                .iter()
+                .position(|(field, _)| *field == name.to_string())
+                .ok_or_else(|| syn::Error::new(name.span(), format!("no field `{}`", name)))?;
+            states[index] = if fields[index].1 {
+                input.parse::<Token![=]>()?;
+                let state = input.parse::<Type>()?;
+                quote!(::builder::Flattened<#state>)
+            } else {
+                quote!(::builder::Set)
+            };
+        }
+        Ok(StateInput {
+            seed,
+            states,
+            callback,
+        })
+    }
+}
+
+impl StateInput {
+    pub fn builder(&self) -> TokenStream {
+        let StateInput { seed, states, .. } = self;
+        let states = quote!((#(#states,)*));
+        let callback = match &self.callback {
+            Some(callback) => quote!(#callback),
+            None => {
+                let built = quote!(<#seed as ::builder::BuilderState<(), #states>>::Built);
+                quote!(fn(#built) -> #built)
+            }
+        };
+        quote!(<#seed as ::builder::BuilderState<#callback, #states>>::Builder)
+    }
+}
+
+/// The input of `__state_macro!`: the fields a builder's state macro lists for `__flatten!`, the
+/// flattened fields whose own lists are still to be added, and the builder's description.
+///
+/// Each flattened field's list is added by invoking its macro with the rest of the input, which
+/// invokes `__state_macro!` again, until the macro can be defined.
+pub struct StateMacroInput {
+    /// Each field's setter name, and its path of indices from the builder.
+    fields: Vec<(Vec<LitInt>, String)>,
+    /// The index, prefix and macro of each flattened field still to be added.
+    pending: Vec<(LitInt, Option<Ident>, Path)>,
+    desc: TokenStream,
+    export: TokenStream,
+    macro_ident: Ident,
+    vis: TokenStream,
+    builder_ident: Ident,
+    arg_pattern: TokenStream,
+    seed: TokenStream,
+    field_specs: TokenStream,
+}
+
+fn parse_fields(list: ParseStream) -> syn::Result<Vec<(Vec<LitInt>, String)>> {
+    let mut fields = Vec::new();
+    while !list.is_empty() {
+        let field;
+        parenthesized!(field in list);
+        if field.parse::<Option<Token![@]>>()?.is_some() {
+            // a flattened field's list, as passed back by its macro
+            field.parse::<Ident>()?;
+            let index = field.parse::<LitInt>()?;
+            let prefix;
+            braced!(prefix in field);
+            let prefix = prefix.parse::<Option<Ident>>()?;
+            let nested;
+            bracketed!(nested in field);
+            fields.extend(parse_fields(&nested)?.into_iter().map(|(path, name)| {
+                let path = once(index.clone()).chain(path).collect();
+                match &prefix {
+                    Some(prefix) => (path, format!("{}_{}", prefix, name)),
+                    None => (path, name),
+                }
+            }));
+            continue;
+        }
+        let path;
+        bracketed!(path in field);
+        let mut indices = Vec::new();
+        while !path.is_empty() {
+            indices.push(path.parse()?);
+        }
+        fields.push((indices, field.parse::<TokenTree>()?.to_string()));
+    }
+    Ok(fields)
+}
+
+impl Parse for StateMacroInput {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let list;
+        bracketed!(list in input);
+        let fields = parse_fields(&list)?;
+        let list;
+        bracketed!(list in input);
+        let mut pending = Vec::new();
+        while !list.is_empty() {
+            let field;
+            parenthesized!(field in list);
+            let index = field.parse()?;
+            let prefix;
+            braced!(prefix in field);
+            pending.push((index, prefix.parse()?, field.parse()?));
+        }
+
+        let desc = input.fork().parse()?;
+        let group = || -> syn::Result<TokenStream> {
+            let group;
+            braced!(group in input);
+            group.parse()
+        };
+        Ok(StateMacroInput {
+            fields,
+            pending,
+            desc,
+            export: group()?,
+            macro_ident: syn::parse2(group()?)?,
+            vis: group()?,
+            builder_ident: syn::parse2(group()?)?,
+            arg_pattern: group()?,
+            seed: group()?,
+            field_specs: group()?,
+        })
+    }
+}
+
+impl StateMacroInput {
+    pub fn state_macro(&self) -> TokenStream {
+        let fields = self.fields.iter().map(|(path, name)| {
+            let name = match name.parse::<u32>() {
+                Ok(_) => name.parse::<TokenStream>().unwrap(),
+                Err(_) => format_ident!("{}", name).into_token_stream(),
+            };
+            quote!(([#(#path)*] #name))
+        });
+        let fields = quote!([#(#fields)*]);
+        if let Some(((index, prefix, child_macro), pending)) = self.pending.split_first() {
+            let pending = pending
+                .iter()
+                .map(|(index, prefix, child_macro)| quote!((#index { #prefix } #child_macro)));
+            let desc = &self.desc;
+            return quote! {
+                #child_macro! { @lift #fields [#(#pending)*] #index { #prefix } #desc }
+            };
+        }
+
+        let StateMacroInput {
+            export,
+            macro_ident,
+            vis,
+            builder_ident,
+            arg_pattern,
+            seed,
+            field_specs,
+            ..
+        } = self;
+        quote! {
+            // the macro is exported even if the struct is declared in a function's body
+            #[doc(hidden)]
+            #[allow(unknown_lints, non_local_definitions)]
+            #export
+            macro_rules! #macro_ident {
+                (@flatten $($parent:tt)*) => {
+                    ::builder::__flatten! { #fields $($parent)* }
+                };
+                (@lift [$($fields:tt)*] $pending:tt $index:tt $prefix:tt $($desc:tt)*) => {
+                    ::builder::__state_macro! {
+                        [$($fields)* (@flattened $index $prefix #fields)] $pending $($desc)*
+                    }
+                };
+                (#arg_pattern $($rest:tt)*) => {
+                    ::builder::__state! { [#seed] [#field_specs] $($rest)* }
+                };
+            }
+
+            // also lets a parent flattening this struct find the macro through the builder's name
+            #[allow(unused_imports)]
+            #vis use #macro_ident as #builder_ident;
+        }
+    }
+}
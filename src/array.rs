@@ -0,0 +1,165 @@
+use core::fmt;
+
+use crate::{Build, BuilderWithCallback, Callback, Failure, TryCallback};
+
+/// Builder for an array, whose elements are set index by index. `S` records whether an
+/// element's `try_build` has failed, see `Failure`.
+pub struct ArrayBuilder<T, F, const N: usize, S = ()> {
+    callback: F,
+    elements: [Option<T>; N],
+    failure: S,
+}
+
+impl<T, F, const N: usize> BuilderWithCallback<F> for [T; N]
+where
+    F: Callback<Self>,
+{
+    type CallbackBuilder = ArrayBuilder<T, F, N>;
+
+    fn builder_with_callback(callback: F) -> Self::CallbackBuilder {
+        ArrayBuilder {
+            callback,
+            elements: core::array::from_fn(|_| None),
+            failure: (),
+        }
+    }
+}
+
+impl<T, F, const N: usize, S> ArrayBuilder<T, F, N, S> {
+    /// Sets the element at `index`, replacing any previous value.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `index` is out of bounds.
+    pub fn set(mut self, index: usize, value: T) -> Self {
+        self.elements[index] = Some(value);
+        self
+    }
+
+    /// Builds the element at `index` with its own builder, replacing any previous value.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `index` is out of bounds. The index is checked when this is called, rather than
+    /// when the element's builder finishes.
+    pub fn build_element(
+        self,
+        index: usize,
+    ) -> <T as BuilderWithCallback<ElementCallback<T, F, N, S>>>::CallbackBuilder
+    where
+        T: BuilderWithCallback<ElementCallback<T, F, N, S>>,
+    {
+        assert!(
+            index < N,
+            "array index {} out of bounds for length {}",
+            index,
+            N
+        );
+        T::builder_with_callback(ElementCallback {
+            parent: self,
+            index,
+        })
+    }
+
+    /// Passes the array to the callback, or the first error of an element's `try_build`, or
+    /// else an `IncompleteArray` with the first index which was never set.
+    pub fn try_build<E>(self) -> <F as TryCallback<[T; N], E>>::Output
+    where
+        F: TryCallback<[T; N], E>,
+        S: Failure<E>,
+        IncompleteArray: Into<E>,
+    {
+        let elements = self.elements;
+        let result = self.failure.into_result().and_then(|()| {
+            match elements.iter().position(Option::is_none) {
+                Some(index) => Err(IncompleteArray { index }.into()),
+                None => Ok(elements.map(|element| element.unwrap())),
+            }
+        });
+        self.callback.try_callback(result)
+    }
+
+    fn map_failure<U>(self, f: impl FnOnce(S) -> U) -> ArrayBuilder<T, F, N, U> {
+        ArrayBuilder {
+            callback: self.callback,
+            elements: self.elements,
+            failure: f(self.failure),
+        }
+    }
+}
+
+impl<T, F, const N: usize> ArrayBuilder<T, F, N> {
+    /// Builds the array.
+    ///
+    /// # Panics
+    ///
+    /// Panics if any element was never set, as the elements set are only known at runtime. Use
+    /// `try_build` to get an `IncompleteArray` error instead.
+    pub fn build(self) -> F::Output
+    where
+        F: Callback<[T; N]>,
+    {
+        if let Some(index) = self.elements.iter().position(Option::is_none) {
+            panic!("{}", IncompleteArray { index });
+        }
+        let elements = self.elements.map(|element| element.unwrap());
+        self.callback.callback(elements)
+    }
+}
+
+impl<T, F, const N: usize> Build for ArrayBuilder<T, F, N>
+where
+    F: Callback<[T; N]>,
+{
+    type Output = F::Output;
+
+    fn build(self) -> Self::Output {
+        ArrayBuilder::build(self)
+    }
+}
+
+/// Callback which stores the built value at an index of its parent `ArrayBuilder`.
+pub struct ElementCallback<T, F, const N: usize, S = ()> {
+    parent: ArrayBuilder<T, F, N, S>,
+    index: usize,
+}
+
+impl<T, F, const N: usize, S> Callback<T> for ElementCallback<T, F, N, S> {
+    type Output = ArrayBuilder<T, F, N, S>;
+
+    fn callback(self, this: T) -> Self::Output {
+        self.parent.set(self.index, this)
+    }
+}
+
+impl<T, F, const N: usize, S, E> TryCallback<T, E> for ElementCallback<T, F, N, S>
+where
+    S: Failure<E>,
+{
+    type Output = ArrayBuilder<T, F, N, Result<(), E>>;
+
+    fn try_callback(self, this: Result<T, E>) -> Self::Output {
+        match this {
+            Ok(element) => self
+                .parent
+                .map_failure(Failure::into_result)
+                .set(self.index, element),
+            Err(error) => self
+                .parent
+                .map_failure(|failure| failure.into_result().and(Err(error))),
+        }
+    }
+}
+
+/// The error of `ArrayBuilder::try_build` when an element was never set.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IncompleteArray {
+    /// The first index which was never set.
+    pub index: usize,
+}
+
+impl fmt::Display for IncompleteArray {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "array element {} was never set", self.index)
+    }
+}
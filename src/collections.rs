@@ -0,0 +1,142 @@
+use alloc::collections::{BTreeMap, BTreeSet, BinaryHeap, LinkedList, VecDeque};
+use alloc::vec::Vec;
+
+use crate::{Build, BuilderWithCallback, Callback, Failure, TryCallback};
+
+/// Builder for a collection, which accumulates elements until `build` passes the collection to
+/// the callback. `S` records whether an element's `try_build` has failed, see `Failure`.
+pub struct CollectionBuilder<C, F, S = ()> {
+    collection: C,
+    callback: F,
+    failure: S,
+}
+
+impl<C, F, S> CollectionBuilder<C, F, S> {
+    pub fn push<T>(mut self, item: T) -> Self
+    where
+        C: Extend<T>,
+    {
+        self.collection.extend(core::iter::once(item));
+        self
+    }
+
+    pub fn extend<I>(mut self, items: I) -> Self
+    where
+        I: IntoIterator,
+        C: Extend<I::Item>,
+    {
+        self.collection.extend(items);
+        self
+    }
+
+    pub fn build_push(
+        self,
+    ) -> <C::Item as BuilderWithCallback<PushCallback<C, F, S>>>::CallbackBuilder
+    where
+        C: IntoIterator + Extend<C::Item>,
+        C::Item: BuilderWithCallback<PushCallback<C, F, S>>,
+    {
+        C::Item::builder_with_callback(PushCallback { parent: self })
+    }
+
+    /// Passes the collection, or the first error of an element's `try_build`, to the callback.
+    pub fn try_build<E>(self) -> <F as TryCallback<C, E>>::Output
+    where
+        F: TryCallback<C, E>,
+        S: Failure<E>,
+    {
+        let collection = self.collection;
+        let result = self.failure.into_result().map(|()| collection);
+        self.callback.try_callback(result)
+    }
+
+    fn map_failure<T>(self, f: impl FnOnce(S) -> T) -> CollectionBuilder<C, F, T> {
+        CollectionBuilder {
+            collection: self.collection,
+            callback: self.callback,
+            failure: f(self.failure),
+        }
+    }
+}
+
+impl<C, F> CollectionBuilder<C, F> {
+    pub fn build(self) -> F::Output
+    where
+        F: Callback<C>,
+    {
+        self.callback.callback(self.collection)
+    }
+}
+
+impl<C, F> Build for CollectionBuilder<C, F>
+where
+    F: Callback<C>,
+{
+    type Output = F::Output;
+
+    fn build(self) -> Self::Output {
+        CollectionBuilder::build(self)
+    }
+}
+
+/// Callback which pushes the built element onto its parent `CollectionBuilder`.
+pub struct PushCallback<C, F, S = ()> {
+    parent: CollectionBuilder<C, F, S>,
+}
+
+impl<C, F, S> Callback<C::Item> for PushCallback<C, F, S>
+where
+    C: IntoIterator + Extend<C::Item>,
+{
+    type Output = CollectionBuilder<C, F, S>;
+
+    fn callback(self, this: C::Item) -> Self::Output {
+        self.parent.push(this)
+    }
+}
+
+impl<C, F, S, E> TryCallback<C::Item, E> for PushCallback<C, F, S>
+where
+    C: IntoIterator + Extend<C::Item>,
+    S: Failure<E>,
+{
+    type Output = CollectionBuilder<C, F, Result<(), E>>;
+
+    fn try_callback(self, this: Result<C::Item, E>) -> Self::Output {
+        match this {
+            Ok(item) => self.parent.map_failure(Failure::into_result).push(item),
+            Err(error) => self
+                .parent
+                .map_failure(|failure| failure.into_result().and(Err(error))),
+        }
+    }
+}
+
+macro_rules! impl_collection_builder {
+    ($($collection:ident <$($param:ident),*> $(where $($bound:ident: $trait:path),*)?;)*) => {$(
+        impl<$($param,)* F> BuilderWithCallback<F> for $collection<$($param),*>
+        where
+            F: Callback<Self>,
+            $($($bound: $trait,)*)?
+        {
+            type CallbackBuilder = CollectionBuilder<Self, F>;
+
+            fn builder_with_callback(callback: F) -> Self::CallbackBuilder {
+                CollectionBuilder {
+                    collection: Default::default(),
+                    callback,
+                    failure: (),
+                }
+            }
+        }
+    )*};
+}
+
+impl_collection_builder! {
+    Vec<T>;
+    VecDeque<T>;
+    LinkedList<T>;
+    BinaryHeap<T> where T: Ord;
+    BTreeSet<T>;
+    BTreeMap<K, V>;
+}
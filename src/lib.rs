@@ -1,14 +1,42 @@
 #![no_std]
 
+#[cfg(feature = "alloc")]
+extern crate alloc;
+
 use core::fmt;
 use core::marker::PhantomData;
 
-pub use builder_derive::Builder;
+#[doc(hidden)]
+pub use builder_derive::{__flatten, __state, __state_macro};
+pub use builder_derive::{builder, Builder};
+
+mod array;
+#[cfg(feature = "alloc")]
+mod collections;
+mod tuples;
+
+pub use array::{ArrayBuilder, ElementCallback, IncompleteArray};
+#[cfg(feature = "alloc")]
+pub use collections::{CollectionBuilder, PushCallback};
+pub use tuples::{
+    Tuple10Builder, Tuple11Builder, Tuple12Builder, Tuple1Builder, Tuple2Builder, Tuple3Builder,
+    Tuple4Builder, Tuple5Builder, Tuple6Builder, Tuple7Builder, Tuple8Builder, Tuple9Builder,
+    TupleCallback,
+};
 
 pub trait Builder: Sized {
     type Builder;
 
     fn builder() -> Self::Builder;
+
+    /// Returns a builder which passes the built value through `f`.
+    fn builder_map<F, U>(f: F) -> <Self as BuilderWithCallback<F>>::CallbackBuilder
+    where
+        F: FnOnce(Self) -> U,
+        Self: BuilderWithCallback<F>,
+    {
+        Self::builder_with_callback(f)
+    }
 }
 
 impl<T> Builder for T
@@ -22,6 +50,13 @@ where
     }
 }
 
+/// A builder which has every field it needs and can be built.
+pub trait Build {
+    type Output;
+
+    fn build(self) -> Self::Output;
+}
+
 pub trait BuilderWithCallback<F: Callback<Self>>: Sized {
     type CallbackBuilder;
 
@@ -45,6 +80,272 @@ where
     }
 }
 
+pub trait CallbackExt<T>: Callback<T> + Sized {
+    /// Applies `f` to the output of this callback.
+    fn map<G, U>(self, f: G) -> Map<Self, G>
+    where
+        G: FnOnce(Self::Output) -> U,
+    {
+        Map { callback: self, f }
+    }
+
+    /// Passes the output of this callback on to `next`.
+    fn then<G>(self, next: G) -> Then<Self, G>
+    where
+        G: Callback<Self::Output>,
+    {
+        Then {
+            callback: self,
+            next,
+        }
+    }
+
+    /// Calls `f` with a reference to the built value before passing it on to this callback.
+    fn inspect<G>(self, f: G) -> Inspect<Self, G>
+    where
+        G: FnOnce(&T),
+    {
+        Inspect { callback: self, f }
+    }
+}
+
+impl<T, F> CallbackExt<T> for F where F: Callback<T> {}
+
+pub struct Map<F, G> {
+    callback: F,
+    f: G,
+}
+
+impl<T, U, F, G> Callback<T> for Map<F, G>
+where
+    F: Callback<T>,
+    G: FnOnce(F::Output) -> U,
+{
+    type Output = U;
+
+    fn callback(self, this: T) -> Self::Output {
+        (self.f)(self.callback.callback(this))
+    }
+}
+
+/// Maps the success of a fallible callback, whose output is a `Result`.
+impl<T, E, O, U, F, G> TryCallback<T, E> for Map<F, G>
+where
+    F: TryCallback<T, E, Output = Result<O, E>>,
+    G: FnOnce(O) -> U,
+{
+    type Output = Result<U, E>;
+
+    fn try_callback(self, this: Result<T, E>) -> Self::Output {
+        self.callback.try_callback(this).map(self.f)
+    }
+}
+
+pub struct Then<F, G> {
+    callback: F,
+    next: G,
+}
+
+impl<T, F, G> Callback<T> for Then<F, G>
+where
+    F: Callback<T>,
+    G: Callback<F::Output>,
+{
+    type Output = G::Output;
+
+    fn callback(self, this: T) -> Self::Output {
+        self.next.callback(self.callback.callback(this))
+    }
+}
+
+/// Passes the `Result` of a fallible callback on to `next`, which can also receive a failure.
+impl<T, E, O, F, G> TryCallback<T, E> for Then<F, G>
+where
+    F: TryCallback<T, E, Output = Result<O, E>>,
+    G: TryCallback<O, E>,
+{
+    type Output = G::Output;
+
+    fn try_callback(self, this: Result<T, E>) -> Self::Output {
+        self.next.try_callback(self.callback.try_callback(this))
+    }
+}
+
+pub struct Inspect<F, G> {
+    callback: F,
+    f: G,
+}
+
+impl<T, F, G> Callback<T> for Inspect<F, G>
+where
+    F: Callback<T>,
+    G: FnOnce(&T),
+{
+    type Output = F::Output;
+
+    fn callback(self, this: T) -> Self::Output {
+        (self.f)(&this);
+        self.callback.callback(this)
+    }
+}
+
+impl<T, E, F, G> TryCallback<T, E> for Inspect<F, G>
+where
+    F: TryCallback<T, E>,
+    G: FnOnce(&T),
+{
+    type Output = F::Output;
+
+    fn try_callback(self, this: Result<T, E>) -> Self::Output {
+        if let Ok(this) = &this {
+            (self.f)(this);
+        }
+        self.callback.try_callback(this)
+    }
+}
+
+/// A callback which can also receive the failure of a fallible `try_build`.
+pub trait TryCallback<T, E> {
+    type Output;
+
+    fn try_callback(self, this: Result<T, E>) -> Self::Output;
+}
+
+impl<F, I, O, E> TryCallback<I, E> for F
+where
+    F: FnOnce(I) -> O,
+{
+    type Output = Result<O, E>;
+
+    fn try_callback(self, this: Result<I, E>) -> Self::Output {
+        this.map(self)
+    }
+}
+
+/// Checks a value built by a `#[builder(validate)]` builder, whose `try_build` returns the error.
+pub trait Validate {
+    type Error;
+
+    /// Returns an error if the value is invalid.
+    fn validate(&self) -> Result<(), Self::Error>;
+}
+
+/// Returned by `try_build` when a field is set without a field it `requires`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RequiresError {
+    pub field: &'static str,
+    pub requires: &'static str,
+}
+
+impl fmt::Display for RequiresError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "`{}` requires `{}` to be set", self.field, self.requires)
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl From<RequiresError> for alloc::string::String {
+    fn from(error: RequiresError) -> Self {
+        alloc::string::ToString::to_string(&error)
+    }
+}
+
+impl<T, F> BuilderWithCallback<F> for Option<T>
+where
+    F: Callback<Option<T>>,
+    T: BuilderWithCallback<SomeCallback<F>>,
+{
+    type CallbackBuilder = T::CallbackBuilder;
+
+    fn builder_with_callback(callback: F) -> Self::CallbackBuilder {
+        T::builder_with_callback(SomeCallback(callback))
+    }
+}
+
+/// Callback which wraps the built value in `Some` before passing it on.
+pub struct SomeCallback<F>(F);
+
+impl<T, F> Callback<T> for SomeCallback<F>
+where
+    F: Callback<Option<T>>,
+{
+    type Output = F::Output;
+
+    fn callback(self, this: T) -> Self::Output {
+        self.0.callback(Some(this))
+    }
+}
+
+impl<T, E, F> TryCallback<T, E> for SomeCallback<F>
+where
+    F: TryCallback<Option<T>, E>,
+{
+    type Output = F::Output;
+
+    fn try_callback(self, this: Result<T, E>) -> Self::Output {
+        self.0.try_callback(this.map(Some))
+    }
+}
+
+#[cfg(feature = "alloc")]
+macro_rules! impl_pointer_builder {
+    ($($(#[$attr:meta])* $pointer:ident => $callback:ident,)*) => {$(
+        $(#[$attr])*
+        impl<T, F> BuilderWithCallback<F> for $pointer<T>
+        where
+            F: Callback<$pointer<T>>,
+            T: BuilderWithCallback<$callback<F>>,
+        {
+            type CallbackBuilder = T::CallbackBuilder;
+
+            fn builder_with_callback(callback: F) -> Self::CallbackBuilder {
+                T::builder_with_callback($callback(callback))
+            }
+        }
+
+        #[doc = concat!("Callback which wraps the built value in a `", stringify!($pointer), "` before passing it on.")]
+        $(#[$attr])*
+        pub struct $callback<F>(F);
+
+        $(#[$attr])*
+        impl<T, F> Callback<T> for $callback<F>
+        where
+            F: Callback<$pointer<T>>,
+        {
+            type Output = F::Output;
+
+            fn callback(self, this: T) -> Self::Output {
+                self.0.callback($pointer::new(this))
+            }
+        }
+
+        $(#[$attr])*
+        impl<T, E, F> TryCallback<T, E> for $callback<F>
+        where
+            F: TryCallback<$pointer<T>, E>,
+        {
+            type Output = F::Output;
+
+            fn try_callback(self, this: Result<T, E>) -> Self::Output {
+                self.0.try_callback(this.map($pointer::new))
+            }
+        }
+    )*};
+}
+
+#[cfg(all(feature = "alloc", target_has_atomic = "ptr"))]
+use alloc::sync::Arc;
+#[cfg(feature = "alloc")]
+use alloc::{boxed::Box, rc::Rc};
+
+#[cfg(feature = "alloc")]
+impl_pointer_builder! {
+    Box => BoxCallback,
+    Rc => RcCallback,
+    #[cfg(target_has_atomic = "ptr")]
+    Arc => ArcCallback,
+}
+
 pub struct NoData<T>(PhantomData<T>);
 
 impl<T> NoData<T> {
@@ -71,6 +372,111 @@ impl<T> Default for NoData<T> {
     }
 }
 
+/// The state of a field a `steps` builder has not reached yet, which cannot be set until the
+/// required fields before it are.
+pub struct Locked<T>(PhantomData<T>);
+
+impl<T> Locked<T> {
+    pub const fn new() -> Self {
+        Locked(PhantomData)
+    }
+}
+
+impl<T> fmt::Debug for Locked<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("Locked")
+    }
+}
+
+impl<T> Default for Locked<T> {
+    fn default() -> Self {
+        Locked(PhantomData)
+    }
+}
+
+/// Marks a field as set when naming a partially-built state.
+pub struct Set;
+
+/// Marks a field as unset when naming a partially-built state.
+pub struct Unset;
+
+pub trait FieldState<T> {
+    type State;
+}
+
+impl<T> FieldState<T> for Set {
+    type State = T;
+}
+
+impl<T> FieldState<T> for Unset {
+    type State = NoData<T>;
+}
+
+/// The state of a field of a `steps` builder, which is `Locked` while the required field before
+/// it, in state `P`, is unset.
+#[doc(hidden)]
+pub trait StepState<T, P> {
+    type State;
+}
+
+impl<T, P> StepState<T, P> for Set {
+    type State = T;
+}
+
+impl<T> StepState<T, Set> for Unset {
+    type State = NoData<T>;
+}
+
+impl<T> StepState<T, Unset> for Unset {
+    type State = Locked<T>;
+}
+
+/// Marks a flattened field whose builder is in state `B` when naming a partially-built state.
+pub struct Flattened<B>(PhantomData<B>);
+
+pub trait FlattenState<T> {
+    type State;
+}
+
+impl<T, B> FlattenState<T> for Flattened<B> {
+    type State = B;
+}
+
+impl<T> FlattenState<T> for Unset
+where
+    T: Builder,
+{
+    type State = T::Builder;
+}
+
+/// Maps a tuple of `Set`/`Unset` markers `S` to the builder state with those fields set, using
+/// the callback `C`. Used by the `FooBuilder![...]` macros generated by the derive.
+#[doc(hidden)]
+pub trait BuilderState<C, S> {
+    type Built;
+    type Builder;
+}
+
+/// Sets the `I`th field of a derived builder. Used to lift the setters of a
+/// `#[builder(flatten)]` field into its parent builder.
+#[doc(hidden)]
+pub trait SetField<const I: usize> {
+    type Value;
+    type Output;
+
+    fn set_field(self, value: Self::Value) -> Self::Output;
+}
+
+/// Maps the builder of the `I`th field of a derived builder, a `#[builder(flatten)]` field.
+/// Used to lift the setters of a flattened field's own flattened fields.
+#[doc(hidden)]
+pub trait MapFlattened<const I: usize> {
+    type Field;
+    type Output<N>;
+
+    fn map_flattened<N>(self, f: impl FnOnce(Self::Field) -> N) -> Self::Output<N>;
+}
+
 pub trait OrDefault<T>
 where
     T: Sized,
@@ -95,3 +501,225 @@ where
         T::default()
     }
 }
+
+pub trait TryField<T, E> {
+    fn try_field(self) -> Result<T, E>;
+}
+
+impl<T, E> TryField<T, E> for T {
+    fn try_field(self) -> Result<T, E> {
+        Ok(self)
+    }
+}
+
+impl<T, E> TryField<T, E> for Result<T, E> {
+    fn try_field(self) -> Result<T, E> {
+        self
+    }
+}
+
+pub trait TryOrDefault<T, E> {
+    fn try_or_default(self) -> Result<T, E>;
+}
+
+impl<T, E> TryOrDefault<T, E> for T {
+    fn try_or_default(self) -> Result<T, E> {
+        Ok(self)
+    }
+}
+
+impl<T, E> TryOrDefault<T, E> for Result<T, E> {
+    fn try_or_default(self) -> Result<T, E> {
+        self
+    }
+}
+
+impl<T, E> TryOrDefault<T, E> for NoData<T>
+where
+    T: Default,
+{
+    fn try_or_default(self) -> Result<T, E> {
+        Ok(T::default())
+    }
+}
+
+pub trait OrElse<T> {
+    fn or_else<F>(self, default: F) -> T
+    where
+        F: FnOnce() -> T;
+}
+
+impl<T> OrElse<T> for T {
+    fn or_else<F>(self, _: F) -> T
+    where
+        F: FnOnce() -> T,
+    {
+        self
+    }
+}
+
+impl<T> OrElse<T> for NoData<T> {
+    fn or_else<F>(self, default: F) -> T
+    where
+        F: FnOnce() -> T,
+    {
+        default()
+    }
+}
+
+pub trait TryOrElse<T, E> {
+    fn try_or_else<F>(self, default: F) -> Result<T, E>
+    where
+        F: FnOnce() -> T;
+}
+
+impl<T, E> TryOrElse<T, E> for T {
+    fn try_or_else<F>(self, _: F) -> Result<T, E>
+    where
+        F: FnOnce() -> T,
+    {
+        Ok(self)
+    }
+}
+
+impl<T, E> TryOrElse<T, E> for Result<T, E> {
+    fn try_or_else<F>(self, _: F) -> Result<T, E>
+    where
+        F: FnOnce() -> T,
+    {
+        self
+    }
+}
+
+impl<T, E> TryOrElse<T, E> for NoData<T> {
+    fn try_or_else<F>(self, default: F) -> Result<T, E>
+    where
+        F: FnOnce() -> T,
+    {
+        Ok(default())
+    }
+}
+
+/// Whether a field's state holds a value, checked by `try_build` for `requires` relations.
+pub trait IsSet<T> {
+    fn is_set(&self) -> bool;
+}
+
+impl<T> IsSet<T> for T {
+    fn is_set(&self) -> bool {
+        true
+    }
+}
+
+impl<T, E> IsSet<T> for Result<T, E> {
+    fn is_set(&self) -> bool {
+        true
+    }
+}
+
+impl<T> IsSet<T> for NoData<T> {
+    fn is_set(&self) -> bool {
+        false
+    }
+}
+
+pub trait Accumulate<C, T> {
+    type Output;
+
+    fn accumulate(self, item: T) -> Self::Output;
+}
+
+impl<C, T> Accumulate<C, T> for C
+where
+    C: Extend<T>,
+{
+    type Output = C;
+
+    fn accumulate(mut self, item: T) -> C {
+        self.extend(core::iter::once(item));
+        self
+    }
+}
+
+impl<C, T> Accumulate<C, T> for NoData<C>
+where
+    C: Default + Extend<T>,
+{
+    type Output = C;
+
+    fn accumulate(self, item: T) -> C {
+        C::default().accumulate(item)
+    }
+}
+
+impl<C, T, E> Accumulate<C, T> for Result<C, E>
+where
+    C: Extend<T>,
+{
+    type Output = Self;
+
+    fn accumulate(self, item: T) -> Self {
+        self.map(|collection| collection.accumulate(item))
+    }
+}
+
+/// Accumulates the result of a fallible nested builder, keeping the first error.
+pub trait TryAccumulate<C, T, E> {
+    fn try_accumulate(self, item: Result<T, E>) -> Result<C, E>;
+}
+
+impl<C, T, E> TryAccumulate<C, T, E> for C
+where
+    C: Extend<T>,
+{
+    fn try_accumulate(self, item: Result<T, E>) -> Result<C, E> {
+        item.map(|item| self.accumulate(item))
+    }
+}
+
+impl<C, T, E> TryAccumulate<C, T, E> for NoData<C>
+where
+    C: Default + Extend<T>,
+{
+    fn try_accumulate(self, item: Result<T, E>) -> Result<C, E> {
+        item.map(|item| self.accumulate(item))
+    }
+}
+
+impl<C, T, E> TryAccumulate<C, T, E> for Result<C, E>
+where
+    C: Extend<T>,
+{
+    fn try_accumulate(self, item: Result<T, E>) -> Result<C, E> {
+        self.and_then(|collection| item.map(|item| collection.accumulate(item)))
+    }
+}
+
+/// Whether a nested `try_build` has failed, for the builders which hold their elements directly
+/// rather than in typed field states. `()` until a failure, and then `Result<(), E>` holding
+/// the first error.
+pub trait Failure<E> {
+    fn into_result(self) -> Result<(), E>;
+}
+
+impl<E> Failure<E> for () {
+    fn into_result(self) -> Result<(), E> {
+        Ok(())
+    }
+}
+
+impl<E> Failure<E> for Result<(), E> {
+    fn into_result(self) -> Result<(), E> {
+        self
+    }
+}
+
+pub trait MapEntry {
+    type Key;
+    type Value;
+}
+
+impl<K, V> MapEntry for (K, V) {
+    type Key = K;
+    type Value = V;
+}
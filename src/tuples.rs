@@ -0,0 +1,136 @@
+use crate::{Build, BuilderWithCallback, Callback, NoData, TryCallback, TryField};
+
+/// Callback which stores the built value in slot `I` of its parent tuple builder.
+pub struct TupleCallback<B, const I: usize> {
+    parent: B,
+}
+
+macro_rules! tuple_builders {
+    // each step generates the builder for every slot seen so far, then adds the next slot
+    (@arity [$($slots:tt)*] []) => {};
+    (@arity [$($slots:tt)*] [($name:ident $($slot:tt)*) $($rest:tt)*]) => {
+        tuple_builders!(@builder $name [$($slots)* ($($slot)*)]);
+        tuple_builders!(@arity [$($slots)* ($($slot)*)] [$($rest)*]);
+    };
+    (@builder $name:ident [$(($idx:tt $T:ident $A:ident $set:ident $build:ident))*]) => {
+        pub struct $name<F, $($A),*> {
+            callback: F,
+            slots: ($($A,)*),
+        }
+
+        impl<F, $($T),*> BuilderWithCallback<F> for ($($T,)*)
+        where
+            F: Callback<Self>,
+        {
+            type CallbackBuilder = $name<F, $(NoData<$T>),*>;
+
+            fn builder_with_callback(callback: F) -> Self::CallbackBuilder {
+                $name {
+                    callback,
+                    slots: ($(NoData::<$T>::new(),)*),
+                }
+            }
+        }
+
+        impl<F, $($T),*> $name<F, $($T),*>
+        where
+            F: Callback<($($T,)*)>,
+        {
+            pub fn build(self) -> F::Output {
+                self.callback.callback(self.slots)
+            }
+        }
+
+        impl<F, $($A),*> $name<F, $($A),*> {
+            /// Passes the tuple to the callback, or the first error of a slot's `try_build`.
+            pub fn try_build<E, $($T),*>(self) -> <F as TryCallback<($($T,)*), E>>::Output
+            where
+                F: TryCallback<($($T,)*), E>,
+                $($A: TryField<$T, E>,)*
+            {
+                let slots = self.slots;
+                let result = (move || Ok(($(slots.$idx.try_field()?,)*)))();
+                self.callback.try_callback(result)
+            }
+        }
+
+        impl<F, $($T),*> Build for $name<F, $($T),*>
+        where
+            F: Callback<($($T,)*)>,
+        {
+            type Output = F::Output;
+
+            fn build(self) -> Self::Output {
+                $name::build(self)
+            }
+        }
+
+        tuple_builders!(@slots $name [] [$(($idx $T $A $set $build))*]);
+    };
+    (@slots $name:ident [$($before:tt)*] []) => {};
+    (@slots $name:ident [$($before:tt)*] [$slot:tt $($after:tt)*]) => {
+        tuple_builders!(@slot $name [$($before)*] $slot [$($after)*]);
+        tuple_builders!(@slots $name [$($before)* $slot] [$($after)*]);
+    };
+    (@slot
+        $name:ident
+        [$(($bidx:tt $BT:ident $BA:ident $bset:ident $bbuild:ident))*]
+        ($idx:tt $T:ident $A:ident $set:ident $build:ident)
+        [$(($aidx:tt $AT:ident $AA:ident $aset:ident $abuild:ident))*]
+    ) => {
+        impl<F, $($BA,)* $T, $($AA),*> $name<F, $($BA,)* NoData<$T>, $($AA),*> {
+            pub fn $set(self, value: $T) -> $name<F, $($BA,)* $T, $($AA),*> {
+                $name {
+                    callback: self.callback,
+                    slots: ($(self.slots.$bidx,)* value, $(self.slots.$aidx,)*),
+                }
+            }
+
+            pub fn $build(self) -> <$T as BuilderWithCallback<TupleCallback<Self, $idx>>>::CallbackBuilder
+            where
+                $T: BuilderWithCallback<TupleCallback<Self, $idx>>,
+            {
+                $T::builder_with_callback(TupleCallback { parent: self })
+            }
+        }
+
+        impl<F, $($BA,)* $T, $($AA),*> Callback<$T>
+            for TupleCallback<$name<F, $($BA,)* NoData<$T>, $($AA),*>, $idx>
+        {
+            type Output = $name<F, $($BA,)* $T, $($AA),*>;
+
+            fn callback(self, this: $T) -> Self::Output {
+                self.parent.$set(this)
+            }
+        }
+
+        impl<F, $($BA,)* $T, $($AA,)* E> TryCallback<$T, E>
+            for TupleCallback<$name<F, $($BA,)* NoData<$T>, $($AA),*>, $idx>
+        {
+            type Output = $name<F, $($BA,)* Result<$T, E>, $($AA),*>;
+
+            fn try_callback(self, this: Result<$T, E>) -> Self::Output {
+                let parent = self.parent;
+                $name {
+                    callback: parent.callback,
+                    slots: ($(parent.slots.$bidx,)* this, $(parent.slots.$aidx,)*),
+                }
+            }
+        }
+    };
+}
+
+tuple_builders!(@arity [] [
+    (Tuple1Builder 0 T0 A0 set_0 build_0)
+    (Tuple2Builder 1 T1 A1 set_1 build_1)
+    (Tuple3Builder 2 T2 A2 set_2 build_2)
+    (Tuple4Builder 3 T3 A3 set_3 build_3)
+    (Tuple5Builder 4 T4 A4 set_4 build_4)
+    (Tuple6Builder 5 T5 A5 set_5 build_5)
+    (Tuple7Builder 6 T6 A6 set_6 build_6)
+    (Tuple8Builder 7 T7 A7 set_7 build_7)
+    (Tuple9Builder 8 T8 A8 set_8 build_8)
+    (Tuple10Builder 9 T9 A9 set_9 build_9)
+    (Tuple11Builder 10 T10 A10 set_10 build_10)
+    (Tuple12Builder 11 T11 A11 set_11 build_11)
+]);
@@ -60,6 +60,151 @@ fn build_const_generic() {
     assert_eq!(x, ConstGeneric([32, 44, 61]));
 }
 
+#[derive(Builder, Debug, PartialEq)]
+struct Defaulted<K, V: Default = u32, const N: usize = 2> {
+    key: K,
+    #[builder(default)]
+    values: [V; N],
+}
+
+#[test]
+fn build_defaulted_generics() {
+    let x: Defaulted<&str> = Defaulted::builder().set_key("id").build();
+    assert_eq!(x.values, [0, 0]);
+    let x = Defaulted::<_, char, 3>::builder()
+        .set_values(['a', 'b', 'c'])
+        .set_key(1)
+        .build();
+    assert_eq!(
+        x,
+        Defaulted {
+            key: 1,
+            values: ['a', 'b', 'c'],
+        }
+    );
+}
+
+#[derive(Builder)]
+struct Tokens<I>
+where
+    I: Iterator,
+    I::Item: Clone,
+{
+    source: I,
+    #[builder(default)]
+    peeked: Option<I::Item>,
+    #[builder(default)]
+    seen: Vec<I::Item>,
+}
+
+#[derive(Builder)]
+struct Normalizer<F>
+where
+    F: for<'s> Fn(&'s str) -> &'s str,
+{
+    normalize: F,
+    #[builder(default)]
+    lowercase: bool,
+}
+
+#[test]
+fn build_with_where_clause() {
+    let tokens = Tokens::builder()
+        .set_source([1, 2, 3].into_iter())
+        .set_peeked(Some(0))
+        .build();
+    assert_eq!(tokens.source.collect::<Vec<_>>(), [1, 2, 3]);
+    assert_eq!(tokens.peeked, Some(0));
+    assert!(tokens.seen.is_empty());
+
+    let normalizer = Normalizer::builder().set_normalize(str::trim).build();
+    assert_eq!((normalizer.normalize)("  a "), "a");
+    assert!(!normalizer.lowercase);
+}
+
+#[derive(Builder, Debug, PartialEq)]
+struct Token<'a> {
+    text: &'a str,
+    #[builder(default)]
+    offset: usize,
+}
+
+#[derive(Builder, Debug, PartialEq)]
+struct Record<'a, 'b: 'a, K: ?Sized> {
+    key: &'a K,
+    source: &'b str,
+    head: Token<'a>,
+    #[builder(push = token)]
+    tokens: Vec<Token<'b>>,
+}
+
+fn with_head<'a, 'b>(source: &'b str, head: &'a str) -> Record<'a, 'b, str> {
+    Record::builder()
+        .set_key(head)
+        .set_source(source)
+        .build_head()
+        .set_text(head)
+        .build()
+        .push_token()
+        .set_text(source)
+        .build()
+        .build()
+}
+
+fn shorten<'s, C>(
+    builder: TokenBuilder![<'static> text; C],
+    _: &'s str,
+) -> TokenBuilder![<'s> text; C] {
+    builder
+}
+
+#[test]
+fn build_borrowed() {
+    let source = String::from("let x = 1;");
+    let key = String::from("x");
+    let record = Record::builder()
+        .set_source(&source)
+        .build_head()
+        .set_text("let")
+        .build()
+        .set_key(&*key)
+        .push_token()
+        .set_text(&source[4..5])
+        .set_offset(4)
+        .build()
+        .build();
+    assert_eq!(record.key, "x");
+    assert_eq!(
+        record.head,
+        Token {
+            text: "let",
+            offset: 0
+        }
+    );
+    assert_eq!(
+        record.tokens,
+        [Token {
+            text: "x",
+            offset: 4
+        }]
+    );
+
+    let record = {
+        let head = String::from("head");
+        with_head("static", &head).source
+    };
+    assert_eq!(record, "static");
+
+    // a builder borrowing for `'static` can be used where a shorter borrow is expected
+    let local = String::from("local");
+    let len = (|token: Token| token.text.len()) as fn(Token) -> usize;
+    let tokens = [
+        shorten(Token::builder_map(len).set_text("static"), &local),
+        Token::builder_map(len).set_text(&local),
+    ];
+    assert_eq!(tokens.map(|token| token.build()), [6, 5]);
+}
+
 #[derive(Builder, Debug, PartialEq)]
 struct Nested {
     inner: FieldStruct,
@@ -102,3 +247,1198 @@ fn build_defaults() {
         }
     )
 }
+
+#[derive(Builder, Debug, PartialEq)]
+struct OptionalNested {
+    #[builder(default)]
+    inner: Option<FieldStruct>,
+}
+
+#[test]
+fn build_optional_nested() {
+    let x = OptionalNested::builder()
+        .build_inner()
+        .set_name("emily".into())
+        .set_value(127)
+        .build()
+        .build();
+    assert_eq!(
+        x,
+        OptionalNested {
+            inner: Some(FieldStruct {
+                name: "emily".into(),
+                value: 127,
+            })
+        }
+    );
+    let y = OptionalNested::builder().build();
+    assert_eq!(y, OptionalNested { inner: None });
+}
+
+#[cfg(feature = "alloc")]
+#[derive(Builder, Debug, PartialEq)]
+struct PointerNested {
+    boxed: Box<FieldStruct>,
+    shared: std::rc::Rc<FieldStruct>,
+    atomic: std::sync::Arc<FieldStruct>,
+}
+
+#[cfg(feature = "alloc")]
+#[test]
+fn build_pointer_nested() {
+    let x = PointerNested::builder()
+        .build_boxed()
+        .set_name("emily".into())
+        .set_value(1)
+        .build()
+        .build_shared()
+        .set_name("emily".into())
+        .set_value(2)
+        .build()
+        .build_atomic()
+        .set_name("emily".into())
+        .set_value(3)
+        .build()
+        .build();
+    assert_eq!(
+        x,
+        PointerNested {
+            boxed: Box::new(FieldStruct {
+                name: "emily".into(),
+                value: 1,
+            }),
+            shared: std::rc::Rc::new(FieldStruct {
+                name: "emily".into(),
+                value: 2,
+            }),
+            atomic: std::sync::Arc::new(FieldStruct {
+                name: "emily".into(),
+                value: 3,
+            }),
+        }
+    );
+}
+
+#[derive(Builder, Debug, PartialEq)]
+struct Item {
+    id: u32,
+}
+
+#[derive(Builder, Debug, PartialEq)]
+struct WithItems {
+    name: String,
+    #[builder(push = item)]
+    items: Vec<Item>,
+}
+
+#[test]
+fn build_pushed_items() {
+    let x = WithItems::builder()
+        .push_item()
+        .set_id(1)
+        .build()
+        .set_name("emily".into())
+        .push_item()
+        .set_id(2)
+        .build()
+        .build();
+    assert_eq!(
+        x,
+        WithItems {
+            name: "emily".into(),
+            items: vec![Item { id: 1 }, Item { id: 2 }],
+        }
+    );
+    let y = WithItems::builder().set_name("emily".into()).build();
+    assert_eq!(
+        y,
+        WithItems {
+            name: "emily".into(),
+            items: vec![],
+        }
+    );
+}
+
+#[derive(Builder, Debug, PartialEq)]
+struct Route {
+    path: String,
+    #[builder(default)]
+    weight: u32,
+}
+
+#[derive(Builder, Debug, PartialEq)]
+struct Router {
+    #[builder(insert = route)]
+    routes: std::collections::HashMap<String, Route>,
+    #[builder(insert)]
+    fallbacks: std::collections::BTreeMap<u32, Route>,
+}
+
+#[test]
+fn build_inserted_entries() {
+    let x = Router::builder()
+        .insert_route("home".into())
+        .set_path("/".into())
+        .build()
+        .insert_route("about".into())
+        .set_path("/about".into())
+        .set_weight(2)
+        .build()
+        .insert_fallbacks(404)
+        .set_path("/not-found".into())
+        .build()
+        .build();
+    assert_eq!(
+        x,
+        Router {
+            routes: [
+                (
+                    "home".into(),
+                    Route {
+                        path: "/".into(),
+                        weight: 0,
+                    }
+                ),
+                (
+                    "about".into(),
+                    Route {
+                        path: "/about".into(),
+                        weight: 2,
+                    }
+                ),
+            ]
+            .into_iter()
+            .collect(),
+            fallbacks: [(
+                404,
+                Route {
+                    path: "/not-found".into(),
+                    weight: 0,
+                }
+            )]
+            .into_iter()
+            .collect(),
+        }
+    );
+}
+
+#[cfg(feature = "alloc")]
+#[derive(Builder, Debug, PartialEq)]
+struct Inventory {
+    items: Vec<Item>,
+    tags: std::collections::BTreeSet<String>,
+}
+
+#[cfg(feature = "alloc")]
+#[test]
+fn build_collections() {
+    let x = Inventory::builder()
+        .build_items()
+        .build_push()
+        .set_id(1)
+        .build()
+        .push(Item { id: 2 })
+        .extend([Item { id: 3 }, Item { id: 4 }])
+        .build()
+        .build_tags()
+        .push("b".to_string())
+        .push("a".to_string())
+        .build()
+        .build();
+    assert_eq!(
+        x,
+        Inventory {
+            items: vec![
+                Item { id: 1 },
+                Item { id: 2 },
+                Item { id: 3 },
+                Item { id: 4 }
+            ],
+            tags: ["a".to_string(), "b".to_string()].into_iter().collect(),
+        }
+    );
+    let v = Vec::<u32>::builder().push(1).extend([2, 3]).build();
+    assert_eq!(v, vec![1, 2, 3]);
+}
+
+#[derive(Builder, Debug, PartialEq, Clone, Copy)]
+struct Point {
+    x: i32,
+    y: i32,
+}
+
+#[derive(Builder, Debug, PartialEq)]
+struct Shape {
+    bounds: (Point, Point),
+    corners: [Point; 4],
+}
+
+#[test]
+fn build_tuples_and_arrays() {
+    let origin = Point { x: 0, y: 0 };
+    let x = Shape::builder()
+        .build_bounds()
+        .set_0(origin)
+        .build_1()
+        .set_x(2)
+        .set_y(2)
+        .build()
+        .build()
+        .build_corners()
+        .set(0, origin)
+        .set(1, Point { x: 2, y: 0 })
+        .build_element(2)
+        .set_x(2)
+        .set_y(2)
+        .build()
+        .set(3, Point { x: 0, y: 2 })
+        .build()
+        .build();
+    assert_eq!(
+        x,
+        Shape {
+            bounds: (origin, Point { x: 2, y: 2 }),
+            corners: [
+                origin,
+                Point { x: 2, y: 0 },
+                Point { x: 2, y: 2 },
+                Point { x: 0, y: 2 },
+            ],
+        }
+    );
+    let pair = builder::Build::build(<[u32; 2]>::builder().set(0, 1).set(1, 2));
+    assert_eq!(pair, [1, 2]);
+    let incomplete = <[u32; 3]>::builder().set(0, 1).set(2, 3).try_build();
+    assert_eq!(incomplete, Err(builder::IncompleteArray { index: 1 }));
+}
+
+#[derive(Builder, Debug, PartialEq)]
+#[builder(validate)]
+struct Port {
+    number: u16,
+}
+
+impl builder::Validate for Port {
+    type Error = String;
+
+    fn validate(&self) -> Result<(), String> {
+        if self.number == 0 {
+            Err("port must be nonzero".into())
+        } else {
+            Ok(())
+        }
+    }
+}
+
+#[derive(Builder, Debug, PartialEq)]
+struct Server {
+    name: String,
+    #[builder(default)]
+    port: Option<Port>,
+}
+
+#[test]
+fn try_build_validated() {
+    let ok: Result<Port, String> = Port::builder().set_number(80).try_build();
+    assert_eq!(ok, Ok(Port { number: 80 }));
+    let err: Result<Port, String> = Port::builder().set_number(0).try_build();
+    assert_eq!(err, Err("port must be nonzero".into()));
+}
+
+#[test]
+fn try_build_nested() {
+    let ok: Result<Server, String> = Server::builder()
+        .build_port()
+        .set_number(80)
+        .try_build()
+        .set_name("emily".into())
+        .try_build();
+    assert_eq!(
+        ok,
+        Ok(Server {
+            name: "emily".into(),
+            port: Some(Port { number: 80 }),
+        })
+    );
+    let err: Result<Server, String> = Server::builder()
+        .build_port()
+        .set_number(0)
+        .try_build()
+        .set_name("emily".into())
+        .try_build();
+    assert_eq!(err, Err("port must be nonzero".into()));
+    let infallible: Result<Server, String> = Server::builder().set_name("emily".into()).try_build();
+    assert_eq!(
+        infallible,
+        Ok(Server {
+            name: "emily".into(),
+            port: None,
+        })
+    );
+}
+
+#[derive(Builder, Debug, PartialEq)]
+struct Cluster {
+    #[builder(push = port)]
+    ports: Vec<Port>,
+    pair: [Port; 2],
+    backup: (String, Port),
+}
+
+#[derive(Debug, PartialEq)]
+enum ClusterError {
+    Invalid(String),
+    Incomplete(builder::IncompleteArray),
+}
+
+impl From<String> for ClusterError {
+    fn from(error: String) -> Self {
+        ClusterError::Invalid(error)
+    }
+}
+
+impl From<builder::IncompleteArray> for ClusterError {
+    fn from(error: builder::IncompleteArray) -> Self {
+        ClusterError::Incomplete(error)
+    }
+}
+
+fn cluster(ports: [u16; 4], backup: u16) -> Result<Cluster, ClusterError> {
+    Cluster::builder()
+        .push_port()
+        .set_number(ports[0])
+        .try_build()
+        .push_port()
+        .set_number(ports[1])
+        .try_build()
+        .build_pair()
+        .build_element(0)
+        .set_number(ports[2])
+        .try_build()
+        .build_element(1)
+        .set_number(ports[3])
+        .try_build()
+        .try_build()
+        .build_backup()
+        .set_0("backup".into())
+        .build_1()
+        .set_number(backup)
+        .try_build()
+        .try_build()
+        .try_build()
+}
+
+#[test]
+fn try_build_nested_elements() {
+    assert_eq!(
+        cluster([1, 2, 3, 4], 5),
+        Ok(Cluster {
+            ports: vec![Port { number: 1 }, Port { number: 2 }],
+            pair: [Port { number: 3 }, Port { number: 4 }],
+            backup: ("backup".into(), Port { number: 5 }),
+        })
+    );
+    let invalid = Err(ClusterError::Invalid("port must be nonzero".into()));
+    assert_eq!(cluster([0, 2, 3, 4], 5), invalid);
+    assert_eq!(cluster([1, 2, 3, 0], 5), invalid);
+    assert_eq!(cluster([1, 2, 3, 4], 0), invalid);
+
+    let incomplete: Result<Cluster, ClusterError> = Cluster::builder()
+        .build_pair()
+        .build_element(1)
+        .set_number(1)
+        .try_build()
+        .try_build()
+        .build_backup()
+        .set_0("backup".into())
+        .set_1(Port { number: 5 })
+        .build()
+        .try_build();
+    assert_eq!(
+        incomplete,
+        Err(ClusterError::Incomplete(builder::IncompleteArray {
+            index: 0
+        }))
+    );
+}
+
+#[test]
+fn build_mapped() {
+    let x = FieldStruct::builder_map(std::sync::Arc::new)
+        .set_name("emily".into())
+        .set_value(17)
+        .build();
+    assert_eq!(
+        x,
+        std::sync::Arc::new(FieldStruct {
+            name: "emily".into(),
+            value: 17
+        })
+    );
+
+    let mut registry = Vec::new();
+    let index = FieldStruct::builder_map(|built| {
+        registry.push(built);
+        registry.len() - 1
+    })
+    .set_name("emily".into())
+    .set_value(17)
+    .build();
+    assert_eq!(index, 0);
+    assert_eq!(registry[index].value, 17);
+}
+
+#[test]
+fn build_with_combinators() {
+    use builder::{BuilderWithCallback, CallbackExt};
+
+    let mut seen = None;
+    let callback = (|this: FieldStruct| this)
+        .inspect(|this: &FieldStruct| seen = Some(this.value))
+        .map(Box::new)
+        .then(|boxed: Box<FieldStruct>| boxed.name);
+    let x = FieldStruct::builder_with_callback(callback)
+        .set_name("emily".into())
+        .set_value(17)
+        .build();
+    assert_eq!(x, "emily");
+    assert_eq!(seen, Some(17));
+}
+
+#[test]
+fn try_build_with_combinators() {
+    use builder::{BuilderWithCallback, CallbackExt};
+
+    let server = |port| {
+        let callback = (|this: Server| this)
+            .map(|this| this.port)
+            .then(|port: Option<Port>| port.map(|port| port.number));
+        Server::builder_with_callback(callback)
+            .set_name("emily".into())
+            .build_port()
+            .set_number(port)
+            .try_build()
+            .try_build()
+    };
+    let ok: Result<Option<u16>, String> = server(80);
+    assert_eq!(ok, Ok(Some(80)));
+    let err: Result<Option<u16>, String> = server(0);
+    assert_eq!(err, Err("port must be nonzero".into()));
+}
+
+fn describe(config: impl Into<FieldStruct>) -> String {
+    let config = config.into();
+    format!("{}={}", config.name, config.value)
+}
+
+fn finish<B: builder::Build<Output = WithDefaults>>(builder: B) -> WithDefaults {
+    builder.build()
+}
+
+#[test]
+fn build_through_trait() {
+    let described = describe(
+        FieldStruct::builder()
+            .set_name("emily".into())
+            .set_value(17),
+    );
+    assert_eq!(described, "emily=17");
+    let x = finish(WithDefaults::builder().set_name("emily".into()));
+    assert_eq!(
+        x,
+        WithDefaults {
+            name: "emily".into(),
+            value: 0,
+        }
+    );
+}
+
+mod partial {
+    use super::{ConstGenericBuilder, FieldStruct, FieldStructBuilder};
+    use builder::Builder;
+
+    pub struct Holder {
+        pub named: FieldStructBuilder![name],
+    }
+
+    pub fn named(name: &str) -> Holder {
+        Holder {
+            named: FieldStruct::builder().set_name(name.into()),
+        }
+    }
+
+    pub fn unset() -> FieldStructBuilder![] {
+        FieldStruct::builder()
+    }
+
+    pub fn complete() -> FieldStructBuilder![value, name; fn(FieldStruct) -> FieldStruct] {
+        unset().set_value(3).set_name("emily".into())
+    }
+
+    pub fn array() -> ConstGenericBuilder![<u32, 2> 0] {
+        super::ConstGeneric::builder().set_0([1, 2])
+    }
+}
+
+#[test]
+fn build_named_states() {
+    let x = partial::named("emily").named.set_value(17).build();
+    assert_eq!(
+        x,
+        FieldStruct {
+            name: "emily".into(),
+            value: 17,
+        }
+    );
+    assert_eq!(partial::complete().build().value, 3);
+    assert_eq!(partial::array().build(), ConstGeneric([1, 2]));
+}
+
+mod external_partial {
+    // the macro names the builder, so both are imported
+    use builder::Builder;
+    use external::{Endpoint, EndpointBuilder};
+
+    pub fn host(host: &str) -> EndpointBuilder![host] {
+        Endpoint::builder().set_host(host.into())
+    }
+
+    pub fn complete() -> EndpointBuilder![host, port] {
+        host("localhost").set_port(8080)
+    }
+}
+
+#[test]
+fn build_named_states_across_crates() {
+    assert_eq!(
+        external_partial::complete().build(),
+        external::Endpoint {
+            host: "localhost".into(),
+            port: 8080,
+        }
+    );
+    assert_eq!(external_partial::host("example.com").build().port, 80);
+}
+
+#[derive(Builder, Debug, PartialEq)]
+struct Job {
+    name: String,
+    #[builder(field_trait)]
+    timeout: u32,
+}
+
+#[derive(Builder, Debug, PartialEq)]
+struct Probe {
+    #[builder(field_trait = self)]
+    timeout: u32,
+    target: String,
+}
+
+mod batch {
+    #[derive(builder::Builder, Debug, PartialEq)]
+    pub struct Batch {
+        #[builder(field_trait = super)]
+        pub timeout: u32,
+        pub size: usize,
+    }
+}
+
+fn with_common<B: SetTimeout<Value = u32>>(builder: B) -> B::Output {
+    builder.set_timeout(30)
+}
+
+fn doubled_timeout<B: HasTimeout<Value = u32>>(builder: &B) -> u32 {
+    builder.get_timeout() * 2
+}
+
+#[test]
+fn build_with_field_traits() {
+    let job = with_common(Job::builder()).set_name("backup".into());
+    assert_eq!(doubled_timeout(&job), 60);
+    assert_eq!(
+        job.build(),
+        Job {
+            name: "backup".into(),
+            timeout: 30,
+        }
+    );
+    let probe = with_common(Probe::builder())
+        .set_target("localhost".into())
+        .build();
+    assert_eq!(
+        probe,
+        Probe {
+            timeout: 30,
+            target: "localhost".into(),
+        }
+    );
+    let batch = with_common(batch::Batch::builder()).set_size(8);
+    assert_eq!(doubled_timeout(&batch), 60);
+    assert_eq!(
+        batch.build(),
+        batch::Batch {
+            timeout: 30,
+            size: 8,
+        }
+    );
+}
+
+#[test]
+fn get_set_fields() {
+    let partial = FieldStruct::builder().set_value(17);
+    assert_eq!(*partial.get_value(), 17);
+    let partial = partial.set_name("emily".into());
+    assert_eq!(partial.get_name(), "emily");
+    let defaulted = WithDefaults::builder().set_value(3);
+    assert_eq!(*defaulted.get_value(), 3);
+}
+
+#[derive(Builder, Debug, PartialEq)]
+struct Pool {
+    #[builder(default = |b| format!("pool-{}", b.max_idle))]
+    name: String,
+    #[builder(default = |b| b.max_size / 2)]
+    max_idle: u32,
+    #[builder(default = 16)]
+    max_size: u32,
+}
+
+#[test]
+fn build_computed_defaults() {
+    assert_eq!(
+        Pool::builder().set_max_size(10).build(),
+        Pool {
+            name: "pool-5".into(),
+            max_idle: 5,
+            max_size: 10,
+        }
+    );
+    assert_eq!(
+        Pool::builder().set_max_idle(1).build(),
+        Pool {
+            name: "pool-1".into(),
+            max_idle: 1,
+            max_size: 16,
+        }
+    );
+    let pool: Result<Pool, ()> = Pool::builder().set_name("main".into()).try_build();
+    assert_eq!(pool.unwrap().max_idle, 8);
+}
+
+#[derive(Builder, Debug, PartialEq)]
+struct Account {
+    user: String,
+    #[builder(default = |b| b.user.clone())]
+    display_name: String,
+    #[builder(default = |b| format!("{}-{}", b.display_name.to_lowercase(), b.user.len()))]
+    slug: String,
+}
+
+#[test]
+fn build_defaults_from_owned_fields() {
+    assert_eq!(
+        Account::builder().set_user("emily".into()).build(),
+        Account {
+            user: "emily".into(),
+            display_name: "emily".into(),
+            slug: "emily-5".into(),
+        }
+    );
+    let account: Result<Account, ()> = Account::builder()
+        .set_display_name("Emily".into())
+        .set_user("em".into())
+        .try_build();
+    assert_eq!(account.unwrap().slug, "emily-2");
+}
+
+// the closures and patterns binding `b` again read their own `b`, not a field
+#[derive(Builder, Debug, PartialEq)]
+struct Stock {
+    items: Vec<(String, u32)>,
+    #[builder(default = |b| b.items.iter().map(|b| b.1).sum())]
+    total: u32,
+    #[builder(default = |b| match b.items.first() {
+        Some(b) => b.1,
+        None => 0,
+    })]
+    first: u32,
+}
+
+#[test]
+fn build_defaults_with_shadowed_parameter() {
+    let stock = Stock::builder()
+        .set_items(vec![("apples".into(), 3), ("pears".into(), 4)])
+        .build();
+    assert_eq!((stock.total, stock.first), (7, 3));
+}
+
+#[derive(Builder, Debug, PartialEq)]
+struct Canvas<T> {
+    width: u32,
+    height: u32,
+    #[builder(skip = |b| b.width * b.height)]
+    area: u32,
+    #[builder(skip = vec![0; 4])]
+    palette: Vec<u8>,
+    #[builder(skip)]
+    marker: std::marker::PhantomData<T>,
+}
+
+#[test]
+fn build_skipped_fields() {
+    let canvas = Canvas::<()>::builder().set_width(3).set_height(4).build();
+    assert_eq!(
+        canvas,
+        Canvas {
+            width: 3,
+            height: 4,
+            area: 12,
+            palette: vec![0; 4],
+            marker: std::marker::PhantomData,
+        }
+    );
+}
+
+#[derive(Builder, Debug, PartialEq)]
+struct NetConfig {
+    host: String,
+    #[builder(default = 80)]
+    port: u16,
+}
+
+#[derive(Builder, Debug, PartialEq)]
+struct TlsConfig {
+    #[builder(default)]
+    enabled: bool,
+    #[builder(default)]
+    port: u16,
+}
+
+#[derive(Builder, Debug, PartialEq)]
+struct ServerConfig {
+    name: String,
+    #[builder(flatten)]
+    net: NetConfig,
+    #[builder(flatten, prefix = tls)]
+    tls: TlsConfig,
+}
+
+#[test]
+fn build_flattened() {
+    let config = ServerConfig::builder()
+        .set_host("localhost".into())
+        .set_tls_port(443)
+        .set_name("web".into())
+        .build();
+    assert_eq!(
+        config,
+        ServerConfig {
+            name: "web".into(),
+            net: NetConfig {
+                host: "localhost".into(),
+                port: 80,
+            },
+            tls: TlsConfig {
+                enabled: false,
+                port: 443,
+            },
+        }
+    );
+    let partial: ServerConfigBuilder![net = NetConfigBuilder![host]] =
+        ServerConfig::builder().set_host("example.com".into());
+    assert_eq!(
+        partial.set_name("api".into()).set_port(8080).build().net,
+        NetConfig {
+            host: "example.com".into(),
+            port: 8080,
+        }
+    );
+}
+
+mod limits {
+    use builder::Builder;
+
+    #[derive(Builder, Debug, PartialEq)]
+    pub struct Limits {
+        #[builder(default = 100)]
+        pub rate: u32,
+        #[builder(default = 10)]
+        pub burst: u32,
+    }
+
+    #[derive(Builder, Debug, PartialEq)]
+    pub struct Quota {
+        pub owner: String,
+        #[builder(flatten)]
+        pub limits: Limits,
+    }
+}
+
+// a flattened field's builder is imported with it, under the matching name
+use limits::{Limits as RateLimits, LimitsBuilder as RateLimitsBuilder};
+use limits::{Quota, QuotaBuilder};
+
+#[derive(Builder, Debug, PartialEq)]
+struct Gateway {
+    #[builder(flatten, prefix = quota)]
+    quota: Quota,
+    #[builder(flatten, prefix = global)]
+    global: RateLimits,
+    #[builder(flatten, prefix = upstream)]
+    upstream: external::Endpoint,
+}
+
+#[test]
+fn build_flattened_imported() {
+    let gateway = Gateway::builder()
+        .set_quota_owner("emily".into())
+        .set_quota_rate(5)
+        .set_global_burst(50)
+        .set_upstream_host("localhost".into())
+        .build();
+    assert_eq!(
+        gateway,
+        Gateway {
+            quota: Quota {
+                owner: "emily".into(),
+                limits: RateLimits { rate: 5, burst: 10 },
+            },
+            global: RateLimits {
+                rate: 100,
+                burst: 50,
+            },
+            upstream: external::Endpoint {
+                host: "localhost".into(),
+                port: 80,
+            },
+        }
+    );
+}
+
+// each public struct's state macro is exported from the crate root under its own name
+mod first {
+    #[derive(builder::Builder, Debug, PartialEq)]
+    pub struct Twin {
+        pub x: u32,
+    }
+}
+
+mod second {
+    #[derive(builder::Builder, Debug, PartialEq)]
+    pub struct Twin {
+        pub x: u32,
+    }
+
+    // the struct's name is left free for a macro of the same name
+    #[allow(unused_macros)]
+    macro_rules! Twin {
+        () => {};
+    }
+}
+
+#[test]
+fn build_same_named_structs() {
+    {
+        use first::TwinBuilder;
+        let partial: TwinBuilder![x] = first::Twin::builder().set_x(1);
+        assert_eq!(partial.build(), first::Twin { x: 1 });
+    }
+    {
+        use second::TwinBuilder;
+        let partial: TwinBuilder![x] = second::Twin::builder().set_x(2);
+        assert_eq!(partial.build(), second::Twin { x: 2 });
+    }
+}
+
+#[derive(Builder, Debug, PartialEq)]
+struct Auth {
+    user: String,
+    #[builder(group = "credential", exactly_one)]
+    password: Option<String>,
+    #[builder(group = "credential")]
+    token: Option<u64>,
+}
+
+#[test]
+fn build_exactly_one() {
+    let auth = Auth::builder()
+        .set_token(7)
+        .set_user("emily".into())
+        .build();
+    assert_eq!(
+        auth,
+        Auth {
+            user: "emily".into(),
+            password: None,
+            token: Some(7),
+        }
+    );
+    let auth: Result<Auth, ()> = Auth::builder()
+        .set_user("emily".into())
+        .set_password("hunter2".into())
+        .try_build();
+    assert_eq!(auth.unwrap().password.as_deref(), Some("hunter2"));
+    let partial: AuthBuilder![credential] = Auth::builder().set_password("x".into());
+    assert_eq!(partial.set_user("a".into()).build().token, None);
+}
+
+#[derive(Builder, Debug, PartialEq)]
+struct Filter {
+    #[builder(group = "pattern", at_least_one)]
+    include: Option<String>,
+    #[builder(group = "pattern")]
+    exclude: Option<String>,
+}
+
+#[derive(Builder, Debug, PartialEq)]
+struct TlsFiles {
+    #[builder(default, requires = cert)]
+    key: String,
+    #[builder(default)]
+    cert: String,
+    #[builder(default, conflicts_with = ca_bundle)]
+    insecure: bool,
+    #[builder(default)]
+    ca_bundle: String,
+}
+
+#[test]
+fn build_related_fields() {
+    let filter = Filter::builder()
+        .set_exclude("*.tmp".into())
+        .set_include("*.rs".into())
+        .build();
+    assert_eq!(
+        filter,
+        Filter {
+            include: Some("*.rs".into()),
+            exclude: Some("*.tmp".into()),
+        }
+    );
+    let files = TlsFiles::builder()
+        .set_key("key.pem".into())
+        .set_cert("cert.pem".into())
+        .set_insecure(true)
+        .build();
+    assert_eq!(files.cert, "cert.pem");
+    let files: Result<TlsFiles, builder::RequiresError> =
+        TlsFiles::builder().set_key("key.pem".into()).try_build();
+    assert_eq!(
+        files.unwrap_err().to_string(),
+        "`key` requires `cert` to be set"
+    );
+}
+
+#[cfg(feature = "alloc")]
+#[test]
+fn try_build_requires_into_string() {
+    let files: Result<TlsFiles, String> = TlsFiles::builder().set_key("key.pem".into()).try_build();
+    assert_eq!(files, Err("`key` requires `cert` to be set".into()));
+}
+
+#[derive(Builder, Debug, PartialEq)]
+#[builder(steps)]
+struct Query {
+    table: String,
+    #[builder(default)]
+    limit: Option<u32>,
+    #[builder(push = column)]
+    columns: Vec<Item>,
+    filter: String,
+}
+
+#[test]
+fn build_in_steps() {
+    let query = Query::builder()
+        .set_table("users".into())
+        .set_filter("active".into())
+        .push_column()
+        .set_id(1)
+        .build()
+        .set_limit(Some(10))
+        .push_column()
+        .set_id(2)
+        .build()
+        .build();
+    assert_eq!(
+        query,
+        Query {
+            table: "users".into(),
+            limit: Some(10),
+            columns: vec![Item { id: 1 }, Item { id: 2 }],
+            filter: "active".into(),
+        }
+    );
+    let start: QueryBuilder![] = Query::builder();
+    let partial: QueryBuilder![table] = start.set_table("posts".into());
+    let limited: QueryBuilder![table, filter, limit] =
+        partial.set_filter("all".into()).set_limit(Some(1));
+    assert_eq!(limited.build().limit, Some(1));
+}
+
+#[derive(Builder, Debug, PartialEq)]
+#[builder(steps)]
+struct Login {
+    user: String,
+    #[builder(group = "secret", exactly_one)]
+    password: Option<String>,
+    #[builder(group = "secret")]
+    key: Option<u64>,
+    #[builder(default, field_trait)]
+    remember: bool,
+}
+
+fn remembered<B: SetRemember<Value = bool>>(builder: B) -> B::Output {
+    builder.set_remember(true)
+}
+
+#[test]
+fn build_groups_and_field_traits_in_steps() {
+    let login = remembered(Login::builder().set_user("emily".into()).set_key(7)).build();
+    assert_eq!(
+        login,
+        Login {
+            user: "emily".into(),
+            password: None,
+            key: Some(7),
+            remember: true,
+        }
+    );
+}
+
+#[derive(Builder, Debug, PartialEq)]
+#[builder(steps)]
+struct Listener {
+    name: String,
+    port: Port,
+    backlog: u32,
+    #[builder(default)]
+    reuse: bool,
+}
+
+#[test]
+fn try_build_in_steps() {
+    let listener = |port| {
+        Listener::builder()
+            .set_name("web".into())
+            .build_port()
+            .set_number(port)
+            .try_build()
+            .set_backlog(16)
+            .set_reuse(true)
+            .try_build()
+    };
+    let ok: Result<Listener, String> = listener(80);
+    assert_eq!(
+        ok,
+        Ok(Listener {
+            name: "web".into(),
+            port: Port { number: 80 },
+            backlog: 16,
+            reuse: true,
+        })
+    );
+    let err: Result<Listener, String> = listener(0);
+    assert_eq!(err, Err("port must be nonzero".into()));
+}
+
+mod shared {
+    #[derive(Debug, PartialEq)]
+    pub struct Settings {
+        pub name: String,
+        pub level: u32,
+    }
+}
+
+#[derive(Builder)]
+#[builder(remote = "shared::Settings")]
+struct SettingsDef {
+    name: String,
+    #[builder(default = 3)]
+    level: u32,
+}
+
+#[test]
+fn build_remote() {
+    let settings = SettingsDef::builder().set_name("base".into()).build();
+    assert_eq!(
+        settings,
+        shared::Settings {
+            name: "base".into(),
+            level: 3,
+        }
+    );
+    let settings: shared::Settings = SettingsDef::builder()
+        .set_level(5)
+        .set_name("debug".into())
+        .build();
+    assert_eq!(settings.level, 5);
+}
+
+#[derive(Builder)]
+#[builder(remote = "external::Version")]
+struct VersionDef {
+    major: u32,
+    #[builder(default)]
+    minor: u32,
+}
+
+#[derive(Builder, Debug, PartialEq)]
+struct Release {
+    name: String,
+    version: external::Version,
+}
+
+#[test]
+fn build_remote_field() {
+    // the foreign type has no `build_version`, so the mirror's builder passes it to the parent
+    let partial = Release::builder().set_name("one".into());
+    let release = VersionDef::builder_with_callback(|version| partial.set_version(version))
+        .set_major(1)
+        .build()
+        .build();
+    assert_eq!(
+        release,
+        Release {
+            name: "one".into(),
+            version: external::Version { major: 1, minor: 0 },
+        }
+    );
+}
+
+mod config {
+    use builder::Builder;
+
+    #[derive(Builder, Debug, PartialEq)]
+    #[non_exhaustive]
+    pub struct Config {
+        #[builder(required)]
+        pub name: String,
+        #[builder(default)]
+        pub verbose: bool,
+        #[builder(default = 8)]
+        pub threads: usize,
+    }
+}
+
+#[test]
+fn build_non_exhaustive() {
+    let config = config::Config::builder()
+        .set_name("server".into())
+        .set_threads(2)
+        .build();
+    assert_eq!(config.name, "server");
+    assert!(!config.verbose);
+    assert_eq!(config.threads, 2);
+}
+
+#[test]
+fn build_non_exhaustive_across_crates() {
+    let options = external::Options::builder()
+        .set_verbose(true)
+        .set_name("worker".into())
+        .build();
+    assert_eq!(options.name, "worker");
+    assert!(options.verbose);
+    assert_eq!(options.threads, 8);
+}
@@ -0,0 +1,28 @@
+//! Builders derived in another crate, to test what is usable outside of it.
+
+use builder::Builder;
+
+#[derive(Builder, Debug, PartialEq)]
+pub struct Endpoint {
+    pub host: String,
+    #[builder(default = 80)]
+    pub port: u16,
+}
+
+/// A type without a builder, for remote derives.
+#[derive(Debug, PartialEq)]
+pub struct Version {
+    pub major: u32,
+    pub minor: u32,
+}
+
+#[derive(Builder, Debug, PartialEq)]
+#[non_exhaustive]
+pub struct Options {
+    #[builder(required)]
+    pub name: String,
+    #[builder(default)]
+    pub verbose: bool,
+    #[builder(default = 8)]
+    pub threads: usize,
+}
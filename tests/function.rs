@@ -0,0 +1,94 @@
+use builder::builder;
+
+#[builder]
+fn connect(
+    host: &str,
+    #[builder(default = 80)] port: u16,
+    #[builder(into)] user: String,
+) -> String {
+    format!("{}@{}:{}", user, host, port)
+}
+
+#[test]
+fn call_function() {
+    let url = connect().host("example.com").user("emily").call();
+    assert_eq!(url, "emily@example.com:80");
+    let url = connect().user("emily").port(8080).host("localhost").call();
+    assert_eq!(url, "emily@localhost:8080");
+}
+
+#[derive(builder::Builder, Clone, Debug, PartialEq)]
+struct Item {
+    id: u32,
+}
+
+#[builder]
+fn repeat<T: Clone>(item: T, #[builder(default = 2)] times: usize) -> Vec<T> {
+    vec![item; times]
+}
+
+#[builder]
+fn first_word(text: &str, #[builder(default = ' ')] separator: char) -> &str {
+    text.split(separator).next().unwrap_or_default()
+}
+
+#[test]
+fn call_generic_function() {
+    let items = repeat::<Item>().build_item().set_id(3).build().call();
+    assert_eq!(items, vec![Item { id: 3 }, Item { id: 3 }]);
+    assert_eq!(repeat().item("a").times(3).call(), ["a", "a", "a"]);
+    let text = String::from("hello world");
+    assert_eq!(first_word().text(&text).call(), "hello");
+}
+
+#[derive(Debug, PartialEq)]
+struct Client<T> {
+    base: String,
+    retries: u32,
+    tag: T,
+}
+
+#[builder]
+impl<T: Clone> Client<T> {
+    #[builder]
+    fn new(#[builder(into)] base: String, #[builder(default = 3)] retries: u32, tag: T) -> Self {
+        Client { base, retries, tag }
+    }
+
+    #[builder]
+    fn get(&self, path: &str, #[builder(default)] query: Option<&str>) -> String {
+        match query {
+            Some(query) => format!("{}{}?{}", self.base, path, query),
+            None => format!("{}{}", self.base, path),
+        }
+    }
+
+    #[builder]
+    fn retag<U>(self, tag: U) -> Client<U> {
+        Client {
+            base: self.base,
+            retries: self.retries,
+            tag,
+        }
+    }
+}
+
+#[test]
+fn call_methods() {
+    let client = Client::new_builder()
+        .base("https://example.com")
+        .tag(1)
+        .call();
+    assert_eq!(client, Client::new("https://example.com".into(), 3, 1));
+    assert_eq!(
+        client
+            .get_builder()
+            .path("/users")
+            .query(Some("id=7"))
+            .call(),
+        "https://example.com/users?id=7"
+    );
+    assert_eq!(client.get("/", None), "https://example.com/");
+    let client = client.retag_builder().tag("api").call();
+    assert_eq!(client.tag, "api");
+}
@@ -0,0 +1,43 @@
+//! Code generated for ordinary input compiles without warnings.
+#![deny(warnings)]
+
+use builder::builder;
+
+#[builder]
+fn area(width: u32, height: u32) -> u32 {
+    width * height
+}
+
+#[builder]
+fn echo(value: u32) -> u32 {
+    value
+}
+
+#[builder]
+fn constant(#[builder(default)] _unused: u8) -> u32 {
+    7
+}
+
+#[builder]
+fn discard(value: u8) {
+    let _ = value;
+}
+
+#[test]
+fn call_single_expression_functions() {
+    assert_eq!(area().width(2).height(3).call(), 6);
+    assert_eq!(echo().value(4).call(), 4);
+    assert_eq!(constant().call(), 7);
+    discard().value(0).call();
+}
+
+#[test]
+fn build_public_struct_in_function() {
+    #[derive(builder::Builder, Debug, PartialEq)]
+    pub struct Local {
+        pub x: u32,
+    }
+
+    use builder::Builder;
+    assert_eq!(Local::builder().set_x(1).build(), Local { x: 1 });
+}
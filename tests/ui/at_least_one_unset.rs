@@ -0,0 +1,13 @@
+use builder::Builder;
+
+#[derive(Builder)]
+struct Filter {
+    #[builder(group = "pattern", at_least_one)]
+    include: Option<String>,
+    #[builder(group = "pattern")]
+    exclude: Option<String>,
+}
+
+fn main() {
+    Filter::builder().build();
+}
@@ -0,0 +1,14 @@
+use builder::Builder;
+
+#[derive(Builder)]
+struct TlsFiles {
+    #[builder(default, conflicts_with = ca_bundle)]
+    insecure: bool,
+    #[builder(default, field_trait)]
+    ca_bundle: String,
+}
+
+fn main() {
+    let builder = TlsFiles::builder().set_insecure(true);
+    SetCaBundle::set_ca_bundle(builder, "ca.pem".into());
+}
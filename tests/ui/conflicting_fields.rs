@@ -0,0 +1,15 @@
+use builder::Builder;
+
+#[derive(Builder)]
+struct TlsFiles {
+    #[builder(default, conflicts_with = ca_bundle)]
+    insecure: bool,
+    #[builder(default)]
+    ca_bundle: String,
+}
+
+fn main() {
+    TlsFiles::builder()
+        .set_insecure(true)
+        .set_ca_bundle("ca.pem".into());
+}
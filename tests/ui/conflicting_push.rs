@@ -0,0 +1,18 @@
+use builder::Builder;
+
+#[derive(Builder)]
+struct Item {
+    id: u32,
+}
+
+#[derive(Builder)]
+struct Selection {
+    #[builder(default, conflicts_with = items)]
+    all: bool,
+    #[builder(push = item)]
+    items: Vec<Item>,
+}
+
+fn main() {
+    Selection::builder().set_all(true).push_item().set_id(1).build();
+}
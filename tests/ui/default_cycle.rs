@@ -0,0 +1,11 @@
+use builder::Builder;
+
+#[derive(Builder)]
+struct Pool {
+    #[builder(default = |b| b.max_size / 2)]
+    max_idle: u32,
+    #[builder(default = |b| b.max_idle * 2)]
+    max_size: u32,
+}
+
+fn main() {}
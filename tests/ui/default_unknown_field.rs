@@ -0,0 +1,11 @@
+use builder::Builder;
+
+#[derive(Builder)]
+struct Pool {
+    #[builder(default = |b| format!("pool-{}", b.max_idel))]
+    name: String,
+    #[builder(default = 4)]
+    max_idle: u32,
+}
+
+fn main() {}
@@ -0,0 +1,13 @@
+use builder::Builder;
+
+#[derive(Builder)]
+struct Auth {
+    #[builder(group = "credential", exactly_one)]
+    password: Option<String>,
+    #[builder(group = "credential")]
+    token: Option<u64>,
+}
+
+fn main() {
+    Auth::builder().set_password("hunter2".into()).set_token(7);
+}
@@ -0,0 +1,14 @@
+use builder::Builder;
+
+#[derive(Builder)]
+struct Auth {
+    user: String,
+    #[builder(group = "credential", exactly_one)]
+    password: Option<String>,
+    #[builder(group = "credential")]
+    token: Option<u64>,
+}
+
+fn main() {
+    Auth::builder().set_user("emily".into()).build();
+}
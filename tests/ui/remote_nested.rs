@@ -0,0 +1,17 @@
+use builder::Builder;
+
+#[derive(Builder)]
+#[builder(remote = "external::Version")]
+struct VersionDef {
+    major: u32,
+    minor: u32,
+}
+
+#[derive(Builder)]
+struct Release {
+    version: external::Version,
+}
+
+fn main() {
+    Release::builder().build_version();
+}
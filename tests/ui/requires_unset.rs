@@ -0,0 +1,13 @@
+use builder::Builder;
+
+#[derive(Builder)]
+struct TlsFiles {
+    #[builder(default, requires = cert)]
+    key: String,
+    #[builder(default)]
+    cert: String,
+}
+
+fn main() {
+    TlsFiles::builder().set_key("key.pem".into()).build();
+}
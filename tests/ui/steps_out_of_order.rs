@@ -0,0 +1,14 @@
+use builder::Builder;
+
+#[derive(Builder)]
+#[builder(steps)]
+struct Query {
+    table: String,
+    filter: String,
+    #[builder(default)]
+    limit: Option<u32>,
+}
+
+fn main() {
+    Query::builder().set_filter("active".into());
+}
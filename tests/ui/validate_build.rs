@@ -0,0 +1,23 @@
+use builder::Builder;
+
+#[derive(Builder)]
+#[builder(validate)]
+struct Port {
+    number: u16,
+}
+
+impl builder::Validate for Port {
+    type Error = String;
+
+    fn validate(&self) -> Result<(), String> {
+        if self.number == 0 {
+            Err("port must be nonzero".into())
+        } else {
+            Ok(())
+        }
+    }
+}
+
+fn main() {
+    let _ = Port::builder().set_number(0).build();
+}